@@ -0,0 +1,111 @@
+use std::fmt;
+
+use crate::{Span, message_formatter::{self, Diagnostic, FormatOptions}};
+
+/// Represents an error encountered while rendering a template
+#[derive(PartialEq, Debug, Clone)]
+pub enum RenderError {
+    /// Source could not be tokenized
+    Lex(Box<Diagnostic>),
+    /// Tokens could not be parsed into an AST, contains every syntax error found
+    Parse(Vec<Diagnostic>),
+    /// AST could not be interpreted with the given context
+    Runtime(Box<Diagnostic>),
+    /// Context is not valid JSON
+    Context { message: String },
+    /// A file could not be read or written
+    Io { path: String, message: String }
+}
+
+impl RenderError {
+    /// Returns the diagnostics describing the error, empty for context and IO errors
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            RenderError::Lex(diagnostic) | RenderError::Runtime(diagnostic) => std::slice::from_ref(diagnostic),
+            RenderError::Parse(diagnostics) => diagnostics,
+            RenderError::Context { .. } | RenderError::Io { .. } => &[]
+        }
+    }
+
+    /// Returns the first diagnostic describing the error, `Option::None` for context and IO errors
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        self.diagnostics().first()
+    }
+
+    /// Returns the location in the template source the error occurred at, `Option::None` for
+    /// context and IO errors
+    pub fn span(&self) -> Option<Span> {
+        self.diagnostic().map(|diagnostic| diagnostic.label.span)
+    }
+
+    /// Returns the message describing the error
+    pub fn message(&self) -> &str {
+        match self {
+            RenderError::Context { message } | RenderError::Io { message, .. } => message,
+            _ => self.diagnostic().map_or("", |diagnostic| &diagnostic.message)
+        }
+    }
+
+    /// Returns the error formatted with the lines of `source` it refers to, diagnostics are
+    /// separated by an empty line
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - the template source that failed to render
+    /// * `options` - formatting options
+    pub fn format(&self, source: &[u8], options: &FormatOptions) -> String {
+        if let RenderError::Context { .. } | RenderError::Io { .. } = self {
+            return format!("{}\n", self);
+        }
+        self.diagnostics()
+            .iter()
+            .map(|diagnostic| message_formatter::format(source, diagnostic, options))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            RenderError::Lex(_) => "Lexing",
+            RenderError::Parse(_) => "Parsing",
+            RenderError::Runtime(_) => "Runtime",
+            RenderError::Context { message } => return write!(f, "Context error: {}", message),
+            RenderError::Io { path, message } => return write!(f, "IO error: {}: {}", path, message)
+        };
+        let span = self.span().unwrap_or_default();
+        write!(f, "{} error at line {}, column {}: {}", kind, span.line, span.column, self.message())?;
+        if self.diagnostics().len() > 1 {
+            write!(f, " (and {} more)", self.diagnostics().len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// Represents an error returned by a filter, reported as a runtime error where it was applied
+#[derive(PartialEq, Debug, Clone)]
+pub struct CallError {
+    pub message: String
+}
+
+impl CallError {
+    /// Returns an error with given message
+    /// 
+    /// # Arguments
+    /// 
+    /// * `message` - description of the problem
+    pub fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CallError {}
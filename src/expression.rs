@@ -1,4 +1,4 @@
-use crate::{statement::Statement, Token};
+use crate::{statement::Statement, Token, Span};
 
 // pub trait Evaluatable {
 //     fn evaluate(&self) -> Expression;
@@ -12,20 +12,63 @@ pub enum Expression<'a> {
     Variable(VariableExpression<'a>),
     Unary(UnaryExpression<'a>),
     Binary(BinaryExpression<'a>),
-    Literal(LiteralExpression<'a>)
+    Literal(LiteralExpression<'a>),
+    Filter(FilterExpression<'a>),
+    Test(TestExpression<'a>),
+    FunctionCall(FunctionCallExpression<'a>),
+    Index(IndexExpression<'a>)
+}
+
+impl<'a> Expression<'a> {
+    /// Returns the location of the expression in source
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Call(expression) => expression.span,
+            Expression::TemplateLiteral(expression) => expression.span,
+            Expression::Variable(expression) => expression.span,
+            Expression::Unary(expression) => expression.span,
+            Expression::Binary(expression) => expression.span,
+            Expression::Literal(expression) => expression.span,
+            Expression::Filter(expression) => expression.span,
+            Expression::Test(expression) => expression.span,
+            Expression::FunctionCall(expression) => expression.span,
+            Expression::Index(expression) => expression.span
+        }
+    }
 }
 
 /// Represents an AST for an unary expression
 #[derive(Debug)]
 pub struct UnaryExpression<'a> {
     pub operator: Token<'a>,
-    pub right: Box<Statement<'a>>
+    pub right: Box<Statement<'a>>,
+    pub span: Span
 }
 
+/// Represents an AST for a call expression
 #[derive(Debug)]
 pub struct CallExpression<'a> {
     pub callee: Box<Statement<'a>>,
-    pub name: &'a [u8]
+    pub name: &'a [u8],
+    pub span: Span
+}
+
+/// Represents an AST for a function call expression, e.g. `name(arguments)`, or a method call
+/// expression with a receiver, e.g. `receiver.name(arguments)`
+#[derive(Debug)]
+pub struct FunctionCallExpression<'a> {
+    pub receiver: Option<Box<Statement<'a>>>,
+    pub name: &'a Token<'a>,
+    pub arguments: ArgumentList<'a>,
+    pub span: Span
+}
+
+/// Represents an AST for an index expression, e.g. `object[index]` or `array.0`
+#[derive(Debug)]
+pub struct IndexExpression<'a> {
+    pub object: Box<Statement<'a>>,
+    pub index: Box<Statement<'a>>,
+    pub span: Span
 }
 
 /// Represents an AST for a binary expression
@@ -33,23 +76,53 @@ pub struct CallExpression<'a> {
 pub struct BinaryExpression<'a> {
     pub left: Box<Statement<'a>>,
     pub operator: &'a Token<'a>,
-    pub right: Box<Statement<'a>>
+    pub right: Box<Statement<'a>>,
+    pub span: Span
 }
 
 /// Represents an AST for a template literal expression
 #[derive(Debug)]
 pub struct TemplateLiteralExpression<'a> {
-    pub value: &'a [u8]
+    pub value: &'a [u8],
+    pub span: Span
 }
 
 /// Represents an AST for a variable expression
 #[derive(Debug)]
 pub struct VariableExpression<'a> {
-    pub name: &'a [u8]
+    pub name: &'a [u8],
+    pub span: Span
 }
 
 /// Represents an AST for a literal expression
 #[derive(Debug)]
 pub struct LiteralExpression<'a> {
-    pub token: Token<'a>
+    pub token: Token<'a>,
+    pub span: Span
+}
+
+/// Represents an AST for a filter expression, e.g. `value | name(arguments)`
+#[derive(Debug)]
+pub struct FilterExpression<'a> {
+    pub value: Box<Statement<'a>>,
+    pub name: &'a Token<'a>,
+    pub arguments: ArgumentList<'a>,
+    pub span: Span
+}
+
+/// Represents an AST for a test expression, e.g. `value is not name(arguments)`
+#[derive(Debug)]
+pub struct TestExpression<'a> {
+    pub value: Box<Statement<'a>>,
+    pub name: &'a Token<'a>,
+    pub negated: bool,
+    pub arguments: ArgumentList<'a>,
+    pub span: Span
+}
+
+/// Represents an AST for arguments, positional arguments come before named ones
+#[derive(Debug, Default)]
+pub struct ArgumentList<'a> {
+    pub positional: Vec<Statement<'a>>,
+    pub named: Vec<(&'a [u8], Statement<'a>)>
 }
@@ -0,0 +1,505 @@
+// Filters transform a value at output time, e.g. `{{ name | upper | truncate(20) }}`, functions
+// produce a value from their arguments and tests check a value, e.g. `{{ if count is even }}`
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use serde_json::{Number, Value};
+
+use crate::error::CallError;
+
+/// Signature of a filter, receives the filtered value and the arguments the filter was applied with
+pub type Filter = dyn Fn(Value, &Arguments) -> Result<Value, CallError>;
+
+/// Signature of a function, receives the arguments the function was called with
+pub type Function = dyn Fn(&Arguments) -> Result<Value, CallError>;
+
+/// Signature of a test, receives the tested value and the arguments the test was applied with
+pub type Test = dyn Fn(&Value, &Arguments) -> Result<bool, CallError>;
+
+/// Represents evaluated arguments of a filter, function or test, positional arguments are in the order they were given
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Arguments {
+    pub positional: Vec<Value>,
+    pub named: Vec<(String, Value)>
+}
+
+impl Arguments {
+    /// Returns argument named `name` if given, else positional argument at `index`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - position of the argument
+    /// * `name` - name of the argument
+    pub fn get(&self, index: usize, name: &str) -> Option<&Value> {
+        self.named
+            .iter()
+            .find(|(argument_name, _)| argument_name == name)
+            .map(|(_, value)| value)
+            .or_else(|| self.positional.get(index))
+    }
+}
+
+/// Signature of filters bundled with the engine
+type Builtin = fn(Value, &Arguments) -> Result<Value, CallError>;
+
+/// Filters bundled with the engine
+const BUILTINS: [(&str, Builtin); 23] = [
+    ("upper", upper),
+    ("lower", lower),
+    ("capitalize", capitalize),
+    ("title", title),
+    ("trim", trim),
+    ("truncate", truncate),
+    ("replace", replace),
+    ("split", split),
+    ("join", join),
+    ("length", length),
+    ("first", first),
+    ("last", last),
+    ("reverse", reverse),
+    ("sort", sort),
+    ("sort_by", sort_by),
+    ("unique", unique),
+    ("default", default),
+    ("round", round),
+    ("abs", abs),
+    ("json", json),
+    ("urlencode", urlencode),
+    ("slugify", slugify),
+    ("wordcount", wordcount)
+];
+
+/// Returns the filters bundled with the engine keyed by name
+pub fn builtins() -> HashMap<String, Box<Filter>> {
+    BUILTINS
+        .iter()
+        .map(|(name, filter)| (name.to_string(), Box::new(*filter) as Box<Filter>))
+        .collect()
+}
+
+/// Signature of functions bundled with the engine
+type BuiltinFunction = fn(&Arguments) -> Result<Value, CallError>;
+
+/// Functions bundled with the engine
+const BUILTIN_FUNCTIONS: [(&str, BuiltinFunction); 2] = [
+    ("min", |arguments| extreme(arguments, Ordering::Less)),
+    ("max", |arguments| extreme(arguments, Ordering::Greater))
+];
+
+/// Returns the functions bundled with the engine keyed by name
+pub fn builtin_functions() -> HashMap<String, Box<Function>> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .map(|(name, function)| (name.to_string(), Box::new(*function) as Box<Function>))
+        .collect()
+}
+
+/// Signature of tests bundled with the engine
+type BuiltinTest = fn(&Value, &Arguments) -> Result<bool, CallError>;
+
+/// Tests bundled with the engine
+const BUILTIN_TESTS: [(&str, BuiltinTest); 8] = [
+    ("string", |value, _| Ok(value.is_string())),
+    ("number", |value, _| Ok(value.is_number())),
+    ("boolean", |value, _| Ok(value.is_boolean())),
+    ("array", |value, _| Ok(value.is_array())),
+    ("object", |value, _| Ok(value.is_object())),
+    ("even", |value, _| Ok(integer(value)? % 2 == 0)),
+    ("odd", |value, _| Ok(integer(value)? % 2 != 0)),
+    ("divisibleby", divisibleby)
+];
+
+/// Returns the tests bundled with the engine keyed by name
+pub fn builtin_tests() -> HashMap<String, Box<Test>> {
+    BUILTIN_TESTS
+        .iter()
+        .map(|(name, test)| (name.to_string(), Box::new(*test) as Box<Test>))
+        .collect()
+}
+
+/// Returns name of the type of `value` used in diagnostics
+/// 
+/// # Arguments
+/// 
+/// * `value` - the value to get type name of
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object"
+    }
+}
+
+/// Returns `value` as displayed in templates, strings are unquoted, numbers are in base 10, null is
+/// "null" and other values are JSON
+/// 
+/// # Arguments
+/// 
+/// * `value` - the value to display
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Number(number) => {
+            if number.is_f64() { number.as_f64().unwrap().to_string() } else { number.to_string() }
+        }
+        Value::Null => "null".to_string(),
+        _ => value.to_string()
+    }
+}
+
+/// Returns true if `value` is truthy, null, false, 0, empty strings and empty arrays are falsy
+/// 
+/// # Arguments
+/// 
+/// * `value` - the value to evaluate
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(boolean) => *boolean,
+        Value::Number(number) => number.as_f64().unwrap() != 0.0,
+        Value::String(string) => !string.is_empty(),
+        Value::Array(array) => !array.is_empty(),
+        Value::Object(_) => true
+    }
+}
+
+/// Returns true if `left` equals `right` like `==` in templates, numbers are compared by value so
+/// `1` equals `1.0`
+/// 
+/// # Arguments
+/// 
+/// * `left` - left side of comparison
+/// * `right` - right side of comparison
+pub fn is_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => match (left.as_i64(), right.as_i64()) {
+            (Some(left), Some(right)) => left == right,
+            _ => left.as_f64() == right.as_f64()
+        },
+        _ => left == right
+    }
+}
+
+/// Returns error for `value` not being `expected`
+fn expected(expected: &str, value: &Value) -> CallError {
+    CallError::new(format!("Expected {}, found {}", expected, type_name(value)))
+}
+
+/// Returns `value` as a string, errors if it is not one
+fn string(value: &Value) -> Result<&str, CallError> {
+    value.as_str().ok_or_else(|| expected("string", value))
+}
+
+/// Returns `value` as an integer, errors if it is not one
+fn integer(value: &Value) -> Result<i64, CallError> {
+    value.as_i64().ok_or_else(|| expected("integer", value))
+}
+
+/// Returns `value` as an array, errors if it is not one
+fn array(value: Value) -> Result<Vec<Value>, CallError> {
+    match value {
+        Value::Array(array) => Ok(array),
+        value => Err(expected("array", &value))
+    }
+}
+
+/// Returns string argument at `index` or named `name`, `default` if not given
+fn string_argument<'a>(arguments: &'a Arguments, index: usize, name: &str, default: Option<&'a str>) -> Result<&'a str, CallError> {
+    match arguments.get(index, name) {
+        Some(value) => value.as_str().ok_or_else(|| {
+            CallError::new(format!("Expected string for argument `{}`, found {}", name, type_name(value)))
+        }),
+        None => default.ok_or_else(|| CallError::new(format!("Missing argument `{}`", name)))
+    }
+}
+
+/// Returns integer argument at `index` or named `name`, `default` if not given
+fn integer_argument(arguments: &Arguments, index: usize, name: &str, default: i64) -> Result<i64, CallError> {
+    match arguments.get(index, name) {
+        Some(value) => value.as_i64().ok_or_else(|| {
+            CallError::new(format!("Expected integer for argument `{}`, found {}", name, type_name(value)))
+        }),
+        None => Ok(default)
+    }
+}
+
+/// Returns `number` as a JSON number, errors if it is not finite
+fn from_f64(number: f64) -> Result<Value, CallError> {
+    Number::from_f64(number).map(Value::Number).ok_or_else(|| CallError::new("Result is not a finite number"))
+}
+
+/// Returns ordering of `left` relative to `right`, only numbers and strings can be ordered
+fn compare(left: &Value, right: &Value) -> Result<Ordering, CallError> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => Ok(match (left.as_i64(), right.as_i64()) {
+            (Some(left), Some(right)) => left.cmp(&right),
+            _ => left.as_f64().partial_cmp(&right.as_f64()).unwrap_or(Ordering::Equal)
+        }),
+        (Value::String(left), Value::String(right)) => Ok(left.cmp(right)),
+        _ => Err(CallError::new(format!("Cannot compare {} with {}", type_name(left), type_name(right))))
+    }
+}
+
+/// Sorts `array` by the key returned by `key`, errors if keys cannot be compared
+fn sort_array(mut array: Vec<Value>, key: impl Fn(&Value) -> &Value) -> Result<Value, CallError> {
+    let mut error = None;
+    array.sort_by(|left, right| compare(key(left), key(right)).unwrap_or_else(|compare_error| {
+        error.get_or_insert(compare_error);
+        Ordering::Equal
+    }));
+    match error {
+        Some(error) => Err(error),
+        None => Ok(Value::Array(array))
+    }
+}
+
+/// Converts string to uppercase
+fn upper(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    Ok(Value::String(string(&value)?.to_uppercase()))
+}
+
+/// Converts string to lowercase
+fn lower(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    Ok(Value::String(string(&value)?.to_lowercase()))
+}
+
+/// Converts first character of string to uppercase and the rest to lowercase
+fn capitalize(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    let mut characters = string(&value)?.chars();
+    let capitalized = match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters.as_str().to_lowercase().chars()).collect(),
+        None => String::new()
+    };
+    Ok(Value::String(capitalized))
+}
+
+/// Capitalizes every word of string
+fn title(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    let mut titled = String::new();
+    let mut in_word = false;
+    for character in string(&value)?.chars() {
+        if in_word {
+            titled.extend(character.to_lowercase());
+        } else {
+            titled.extend(character.to_uppercase());
+        }
+        in_word = character.is_alphanumeric() || character == '\'';
+    }
+    Ok(Value::String(titled))
+}
+
+/// Removes leading and trailing whitespace of string
+fn trim(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    Ok(Value::String(string(&value)?.trim().to_string()))
+}
+
+/// Shortens string to at most `length` (default 255) characters including `end` (default "...")
+fn truncate(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let length = integer_argument(arguments, 0, "length", 255)?.max(0) as usize;
+    let end = string_argument(arguments, 1, "end", Some("..."))?;
+    let text = string(&value)?;
+    if text.chars().count() <= length {
+        return Ok(value);
+    }
+    let kept: String = text.chars().take(length.saturating_sub(end.chars().count())).collect();
+    // `end` is shortened too if it is longer than `length`
+    Ok(Value::String(kept.chars().chain(end.chars()).take(length).collect()))
+}
+
+/// Replaces every occurrence of `old` in string with `new`
+fn replace(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let old = string_argument(arguments, 0, "old", None)?;
+    let new = string_argument(arguments, 1, "new", None)?;
+    Ok(Value::String(string(&value)?.replace(old, new)))
+}
+
+/// Splits string into an array on `separator`, on whitespace if not given
+fn split(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let text = string(&value)?;
+    let parts: Vec<Value> = match arguments.get(0, "separator") {
+        Some(_) => {
+            let separator = string_argument(arguments, 0, "separator", None)?;
+            if separator.is_empty() {
+                return Err(CallError::new("Separator cannot be empty"));
+            }
+            text.split(separator).map(Value::from).collect()
+        }
+        None => text.split_whitespace().map(Value::from).collect()
+    };
+    Ok(Value::Array(parts))
+}
+
+/// Joins displayed elements of array with `separator` (default "")
+fn join(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let separator = string_argument(arguments, 0, "separator", Some(""))?;
+    let parts: Vec<String> = array(value)?.iter().map(display).collect();
+    Ok(Value::String(parts.join(separator)))
+}
+
+/// Returns number of characters of string, elements of array or entries of object
+fn length(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    match &value {
+        Value::String(string) => Ok(Value::from(string.chars().count())),
+        Value::Array(array) => Ok(Value::from(array.len())),
+        Value::Object(object) => Ok(Value::from(object.len())),
+        _ => Err(expected("string, array or object", &value))
+    }
+}
+
+/// Returns first element of array or first character of string, null if empty
+fn first(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    match value {
+        Value::String(string) => Ok(string.chars().next().map_or(Value::Null, |character| Value::from(character.to_string()))),
+        value => Ok(array(value)?.into_iter().next().unwrap_or(Value::Null))
+    }
+}
+
+/// Returns last element of array or last character of string, null if empty
+fn last(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    match value {
+        Value::String(string) => Ok(string.chars().next_back().map_or(Value::Null, |character| Value::from(character.to_string()))),
+        value => Ok(array(value)?.pop().unwrap_or(Value::Null))
+    }
+}
+
+/// Reverses array or characters of string
+fn reverse(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    match value {
+        Value::String(string) => Ok(Value::String(string.chars().rev().collect())),
+        value => {
+            let mut array = array(value)?;
+            array.reverse();
+            Ok(Value::Array(array))
+        }
+    }
+}
+
+/// Sorts array of numbers or strings in ascending order
+fn sort(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    sort_array(array(value)?, |element| element)
+}
+
+/// Sorts array of objects in ascending order of attribute `attribute`
+fn sort_by(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let attribute = string_argument(arguments, 0, "attribute", None)?;
+    let array = array(value)?;
+    if let Some(element) = array.iter().find(|element| !element.is_object()) {
+        return Err(expected("array of objects", element));
+    }
+    sort_array(array, |element| &element[attribute])
+}
+
+/// Removes repeated elements of array, keeping the first occurrence, elements are compared like `==`
+fn unique(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    let mut unique: Vec<Value> = Vec::new();
+    for element in array(value)? {
+        if !unique.iter().any(|kept| is_equal(kept, &element)) {
+            unique.push(element);
+        }
+    }
+    Ok(Value::Array(unique))
+}
+
+/// Returns `value` if filtered value is null, or falsy when `boolean` is true
+fn default(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let fallback = arguments.get(0, "value").ok_or_else(|| CallError::new("Missing argument `value`"))?;
+    let boolean = arguments.get(1, "boolean").is_some_and(is_truthy);
+    if value.is_null() || (boolean && !is_truthy(&value)) {
+        return Ok(fallback.clone());
+    }
+    Ok(value)
+}
+
+/// Rounds number to `precision` (default 0) decimal places, integers stay integers
+fn round(value: Value, arguments: &Arguments) -> Result<Value, CallError> {
+    let precision = integer_argument(arguments, 0, "precision", 0)?.clamp(-308, 308) as i32;
+    let number = value.as_f64().ok_or_else(|| expected("number", &value))?;
+    if value.is_i64() && precision >= 0 {
+        return Ok(value);
+    }
+    let scale = 10f64.powi(precision);
+    let rounded = (number * scale).round() / scale;
+    if precision <= 0 && rounded.abs() < i64::MAX as f64 {
+        return Ok(Value::from(rounded as i64));
+    }
+    from_f64(rounded)
+}
+
+/// Returns absolute value of number
+fn abs(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    if let Some(absolute) = value.as_i64().and_then(i64::checked_abs) {
+        return Ok(Value::from(absolute));
+    }
+    if value.is_u64() {
+        return Ok(value);
+    }
+    from_f64(value.as_f64().ok_or_else(|| expected("number", &value))?.abs())
+}
+
+/// Serializes value to JSON
+fn json(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    serde_json::to_string(&value).map(Value::String).map_err(|error| CallError::new(error.to_string()))
+}
+
+/// Percent-encodes string or number for use in URLs, unreserved characters are kept
+fn urlencode(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    let text = match &value {
+        Value::String(_) | Value::Number(_) => display(&value),
+        _ => return Err(expected("string or number", &value))
+    };
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Ok(Value::String(encoded))
+}
+
+/// Converts string to lowercase words separated by `-`, other characters are removed
+fn slugify(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    let mut slug = String::new();
+    for character in string(&value)?.chars().flat_map(char::to_lowercase) {
+        if character.is_alphanumeric() {
+            slug.push(character);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    Ok(Value::String(slug.trim_end_matches('-').to_string()))
+}
+
+/// Returns number of whitespace separated words in string
+fn wordcount(value: Value, _: &Arguments) -> Result<Value, CallError> {
+    Ok(Value::from(string(&value)?.split_whitespace().count()))
+}
+
+/// Checks if integer is divisible by `divisor`
+fn divisibleby(value: &Value, arguments: &Arguments) -> Result<bool, CallError> {
+    let divisor = integer_argument(arguments, 0, "divisor", 0)?;
+    if divisor == 0 {
+        return Err(CallError::new("Divisor cannot be zero"));
+    }
+    Ok(integer(value)?.checked_rem(divisor).unwrap_or(0) == 0)
+}
+
+/// Returns the smallest (`Ordering::Less`) or largest (`Ordering::Greater`) of the positional
+/// arguments, or of the elements of a single array argument
+fn extreme(arguments: &Arguments, ordering: Ordering) -> Result<Value, CallError> {
+    let values = match arguments.positional.as_slice() {
+        [Value::Array(array)] => array.as_slice(),
+        values => values
+    };
+    let mut extreme = values.first().ok_or_else(|| CallError::new("Expected at least one value"))?;
+    for value in &values[1 ..] {
+        if compare(value, extreme)? == ordering {
+            extreme = value;
+        }
+    }
+    Ok(extreme.clone())
+}
@@ -1,18 +1,71 @@
-use std::cell::RefCell;
+use std::{cell::{Cell, RefCell}, cmp::Ordering, collections::HashMap};
 
-use serde_json::{Value};
+use serde_json::{Map, Number, Value};
 
-use crate::{statement::{Statement}, expression::Expression, TokenType};
+use crate::{
+    statement::{Statement, ForStatement},
+    expression::{Expression, BinaryExpression, ArgumentList},
+    filter::{self, Filter, Function, Test, Arguments},
+    error::CallError,
+    TokenType,
+    Options,
+    Span,
+    error::RenderError,
+    message_formatter::Diagnostic
+};
 
 /// Interprets AST
 pub struct Interperter {
     // TODO: can &Value be used?
-    context_stack: RefCell<Vec<Value>>
+    context_stack: RefCell<Vec<Value>>,
+    options: Options,
+    filters: HashMap<String, Box<Filter>>,
+    functions: HashMap<String, Box<Function>>,
+    tests: HashMap<String, Box<Test>>,
+    // set by `break` or `continue` until the enclosing for loop handles it
+    loop_control: Cell<Option<LoopControl>>,
+    // state of the for loops being interpreted, innermost last
+    loop_stack: RefCell<Vec<LoopState>>
+}
+
+/// State of a for loop iteration, `loop` properties are computed from it when used so nested
+/// loops do not copy the items of their enclosing loops
+struct LoopState {
+    index: usize,
+    length: usize,
+    previous: Value,
+    next: Value,
+    /// length of context_stack below the scope of the iteration
+    scope_depth: usize
+}
+
+/// Represents a pending `break` or `continue`
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum LoopControl {
+    Break,
+    Continue
 }
 // &[u8] is used to avoid cloning
 enum ValueOrStr<'a> {
     Value(serde_json::Value),
-    Str(&'a [u8])
+    Str(&'a [u8]),
+    // iterated lazily by for, converted to an array elsewhere
+    Range(Range)
+}
+
+/// Represents integers from `start` in steps of `step`, e.g. `1..=5` or `range(0, n, 2)`
+#[derive(PartialEq, Debug, Copy, Clone)]
+struct Range {
+    start: i64,
+    step: i64,
+    length: usize
+}
+
+impl Range {
+    /// Returns iterator over integers of the range
+    fn iter(self) -> impl Iterator<Item = i64> {
+        (0 .. self.length).map(move |i| (self.start as i128 + i as i128 * self.step as i128) as i64)
+    }
 }
 
 impl<'a> Interperter {
@@ -23,55 +76,144 @@ impl<'a> Interperter {
     /// * `context` - the context of the template
     pub fn new(context: Value) -> Self {
         Self {
-            context_stack: RefCell::new(vec!(context))
+            context_stack: RefCell::new(vec!(context)),
+            options: Options::default(),
+            filters: filter::builtins(),
+            functions: filter::builtin_functions(),
+            tests: filter::builtin_tests(),
+            loop_control: Cell::new(None),
+            loop_stack: RefCell::new(Vec::new())
         }
     }
-    /// Interprets given statements returning resulting String
+
+    /// Returns the interperter with given options
+    /// 
+    /// # Arguments
+    /// 
+    /// * `options` - options changing how templates are interpreted
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Replaces the context of the template, registered filters, functions and tests and options
+    /// are kept so one interperter can render templates with many contexts
+    /// 
+    /// # Arguments
+    /// 
+    /// * `context` - the new context of the template
+    pub fn set_context(&self, context: Value) {
+        self.context_stack.replace(vec!(context));
+    }
+
+    /// Returns options changing how templates are interpreted
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Registers `filter` applied with `{{ value | name(arguments) }}`, replaces any filter with
+    /// the same name including built-in ones
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - name used in templates
+    /// * `filter` - receives the filtered value and arguments, errors are reported as runtime errors
+    pub fn register_filter(&mut self, name: &str, filter: impl Fn(Value, &Arguments) -> Result<Value, CallError> + 'static) {
+        self.filters.insert(name.to_string(), Box::new(filter));
+    }
+
+    /// Registers `function` called with `{{ name(arguments) }}`, replaces any function with the
+    /// same name
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - name used in templates
+    /// * `function` - receives the arguments, errors are reported as runtime errors
+    pub fn register_function(&mut self, name: &str, function: impl Fn(&Arguments) -> Result<Value, CallError> + 'static) {
+        self.functions.insert(name.to_string(), Box::new(function));
+    }
+
+    /// Registers `test` applied with `{{ value is name(arguments) }}`, replaces any test with the
+    /// same name including built-in ones
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - name used in templates
+    /// * `test` - receives the tested value and arguments, errors are reported as runtime errors
+    pub fn register_test(&mut self, name: &str, test: impl Fn(&Value, &Arguments) -> Result<bool, CallError> + 'static) {
+        self.tests.insert(name.to_string(), Box::new(test));
+    }
+
+    /// Interprets given statements returning resulting String, or `RenderError::Runtime` if a
+    /// statement cannot be executed with the current context
     /// 
     /// # Arguments
     ///
     /// * `statements` - Abstract Syntax Tree (AST) Vector to be interpreted
-    pub fn interpret(&self, statements: &Vec<Statement>) -> String {
+    pub fn interpret(&self, statements: &[Statement]) -> Result<String, RenderError> {
+        // variables set in a block are scoped to it
+        let is_scope = statements.iter().any(|statement| matches!(statement, Statement::Set(_)));
+        if is_scope {
+            self.context_stack.borrow_mut().push(Value::Object(Map::new()));
+        }
+        let result = self.interpret_statements(statements);
+        if is_scope {
+            self.context_stack.borrow_mut().pop();
+        }
+        result
+    }
+
+    /// Interprets given statements in the current scope returning resulting String
+    /// 
+    /// # Arguments
+    ///
+    /// * `statements` - Abstract Syntax Tree (AST) Vector to be interpreted
+    fn interpret_statements(&self, statements: &[Statement]) -> Result<String, RenderError> {
         let mut result = String::new();
         for statement in statements {
-            // println!("{:?}", statement);
-            result.push_str(&Self::to_string(self.execute(statement)));
+            result.push_str(&Self::to_string(self.execute(statement)?));
+            // rest of the loop body is skipped after `break` or `continue`
+            if self.loop_control.get().is_some() {
+                break;
+            }
         }
-        result
+        Ok(result)
     }
     
     /// Converts all ValueOrStr values to string represtation
     /// Value::Null converts to "null"
     /// Value::Number converts to base10 string representation
+    /// Value::Bool, Value::Array and Value::Object convert to their JSON representation
     fn to_string(value_or_str: ValueOrStr) -> String {
         // TODO: Return &str?
         match value_or_str {
-            ValueOrStr::Value(value) => {
-                if value.is_string() {
-                    value.as_str().unwrap().to_owned()
-                } else if value.is_number() {
-                    value.as_f64().unwrap().to_string()
-                } else if value.is_null() {
-                    "null".to_string()
-                } else {
-                    todo!("{:?}", value);
-                }
-            }
-            ValueOrStr::Str(value) => String::from_utf8(value.to_vec()).unwrap()
+            ValueOrStr::Value(Value::String(string)) => string,
+            ValueOrStr::Value(value) => filter::display(&value),
+            ValueOrStr::Str(value) => String::from_utf8_lossy(value).into_owned(),
+            range @ ValueOrStr::Range(_) => Self::to_string(ValueOrStr::Value(Self::to_value(range)))
         }
         
     }
 
+    /// Converts ValueOrStr to an owned Value, Str converts to Value::String
+    fn to_value(value_or_str: ValueOrStr) -> Value {
+        match value_or_str {
+            ValueOrStr::Value(value) => value,
+            ValueOrStr::Str(value) => Value::String(String::from_utf8_lossy(value).into_owned()),
+            ValueOrStr::Range(range) => Value::Array(range.iter().map(Value::from).collect())
+        }
+    }
+
     /// Returns the value of the key from current context_stack, starts with top of stack and moves down
-    /// returns Value::Null if not found
+    /// to the first scope containing the key, so a null value shadows outer scopes, returns
+    /// Value::Null if not found
     /// 
     /// # Arguments
     /// 
     /// * `key` - the key to search for
-    fn get(&self, key: &[u8]) -> ValueOrStr {
+    fn get(&self, key: &[u8]) -> ValueOrStr<'a> {
         for stack in self.context_stack.borrow().iter().rev() {
-            let value = &stack[std::str::from_utf8(key).unwrap()];
-            if !value.is_null() {
+            if let Some(value) = stack.get(std::str::from_utf8(key).unwrap()) {
                 return ValueOrStr::Value(value.clone());
             }
         }
@@ -82,106 +224,533 @@ impl<'a> Interperter {
     /// # Arguments
     /// 
     /// * `statement` - An Abstract Syntax Tree (AST) that represents a statement
-    fn execute(&'a self, statement: &'a Statement) -> ValueOrStr {
+    fn execute(&self, statement: &'a Statement) -> Result<ValueOrStr<'a>, RenderError> {
         match statement {
             Statement::Expression(expression) => {
                 match expression {
                     Expression::Binary(binary_expression) => {
-                        let left = self.execute(&binary_expression.left);
-                        let right = self.execute(&binary_expression.right);
-                        if binary_expression.operator.token_type == TokenType::DoubleEquals {
-                            return ValueOrStr::Value(Value::Bool(Self::is_equals(&left, &right)));
-                        } else if binary_expression.operator.token_type == TokenType::ExclaimationEqual {
-                            return ValueOrStr::Value(Value::Bool(!Self::is_equals(&left, &right)));
-                        } else if binary_expression.operator.token_type == TokenType::DoublePipe {
-                            if Self::is_truthy(left) || Self::is_truthy(right) {
-                                ValueOrStr::Value(Value::Bool(true))
-                            } else {
-                                ValueOrStr::Value(Value::Bool(false))
+                        let left = self.execute(&binary_expression.left)?;
+                        let right = self.execute(&binary_expression.right)?;
+                        let operator = binary_expression.operator;
+                        match operator.token_type {
+                            TokenType::DoubleEquals => {
+                                Ok(ValueOrStr::Value(Value::Bool(Self::is_equals(&left, &right))))
+                            }
+                            TokenType::ExclaimationEqual => {
+                                Ok(ValueOrStr::Value(Value::Bool(!Self::is_equals(&left, &right))))
                             }
-                        } else if binary_expression.operator.token_type == TokenType::DoubleAmpersand {
-                            if Self::is_truthy(left) && Self::is_truthy(right) {
-                                ValueOrStr::Value(Value::Bool(true))
-                            } else {
-                                ValueOrStr::Value(Value::Bool(false))
+                            TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual => {
+                                let result = match self.compare(&left, &right, binary_expression.span)? {
+                                    Some(ordering) => match operator.token_type {
+                                        TokenType::Less => ordering == Ordering::Less,
+                                        TokenType::LessEqual => ordering != Ordering::Greater,
+                                        TokenType::Greater => ordering == Ordering::Greater,
+                                        _ => ordering != Ordering::Less
+                                    },
+                                    None => false
+                                };
+                                Ok(ValueOrStr::Value(Value::Bool(result)))
                             }
-                        } else {
-                            todo!();
+                            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash | TokenType::Percent => {
+                                Self::arithmetic(&left, &right, binary_expression)
+                            }
+                            TokenType::DoubleDot | TokenType::DoubleDotEqual => {
+                                let (start, end) = match (&left, &right) {
+                                    (ValueOrStr::Value(Value::Number(start)), ValueOrStr::Value(Value::Number(end)))
+                                        if start.is_i64() && end.is_i64() => (start.as_i64().unwrap(), end.as_i64().unwrap()),
+                                    _ => return Err(RenderError::Runtime(Box::new(
+                                        Diagnostic::new(
+                                            "E0219",
+                                            format!("Range bounds must be integers, found {} and {}", Self::type_name(&left), Self::type_name(&right)),
+                                            binary_expression.span
+                                        )
+                                            .with_label("expected integers")
+                                    )))
+                                };
+                                // `..=` includes end
+                                let end = end as i128 + i128::from(operator.token_type == TokenType::DoubleDotEqual);
+                                self.range(start, end, 1, binary_expression.span)
+                            }
+                            TokenType::DoublePipe => {
+                                Ok(ValueOrStr::Value(Value::Bool(Self::is_truthy(left) || Self::is_truthy(right))))
+                            }
+                            TokenType::DoubleAmpersand => {
+                                Ok(ValueOrStr::Value(Value::Bool(Self::is_truthy(left) && Self::is_truthy(right))))
+                            }
+                            _ => Err(RenderError::Runtime(Box::new(Diagnostic::new(
+                                "E0204",
+                                format!("Unsupported binary operator {}", operator.token_type.description()),
+                                operator.span
+                            ))))
                         }
                     }
                     Expression::Unary(unary_expression) => {
-                        let value = Self::is_truthy(self.execute(&unary_expression.right));
-                        assert_eq!(unary_expression.operator.token_type, TokenType::Exclaimation);
-                        ValueOrStr::Value(Value::Bool(!value))
+                        let value = self.execute(&unary_expression.right)?;
+                        match unary_expression.operator.token_type {
+                            TokenType::Exclaimation => Ok(ValueOrStr::Value(Value::Bool(!Self::is_truthy(value)))),
+                            TokenType::Minus => {
+                                let negated = match &value {
+                                    ValueOrStr::Value(Value::Number(number)) => Self::negate(number),
+                                    _ => None
+                                };
+                                match negated {
+                                    Some(number) => Ok(ValueOrStr::Value(Value::Number(number))),
+                                    None => Err(RenderError::Runtime(Box::new(
+                                        Diagnostic::new("E0205", format!("Cannot negate {}", Self::type_name(&value)), unary_expression.span)
+                                            .with_label("expected a finite number")
+                                    )))
+                                }
+                            }
+                            _ => Err(RenderError::Runtime(Box::new(Diagnostic::new(
+                                "E0204",
+                                format!("Unsupported unary operator {}", unary_expression.operator.token_type.description()),
+                                unary_expression.operator.span
+                            ))))
+                        }
                     }
                     Expression::Call(call_expression) => {
-                        // recurse on callee
-                        let value_or_str = self.execute(&call_expression.callee);
-                        let value;
-                        if let ValueOrStr::Value(i) = value_or_str {
-                            value = i;
-                        } else {
-                            panic!("{:?} is undefined", call_expression.name);
-                        }
-                        // only objects can be called
-                        if !value.is_object() { 
-                            panic!("{:?} is undefined", call_expression.name);
+                        // properties of `loop` are computed without building the whole object
+                        if let Some(level) = self.loop_level(&call_expression.callee) {
+                            return Ok(ValueOrStr::Value(self.loop_property(level, call_expression.name)));
                         }
-                        ValueOrStr::Value(value[std::str::from_utf8(call_expression.name).unwrap()].clone())
+                        // recurse on callee
+                        let value = match self.execute(&call_expression.callee)? {
+                            // only objects can be called
+                            ValueOrStr::Value(value) if value.is_object() => value,
+                            callee => return Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new(
+                                    "E0202",
+                                    format!("{} is undefined", String::from_utf8_lossy(call_expression.name)),
+                                    call_expression.span
+                                )
+                                    .with_label(format!("property of {}", Self::type_name(&callee)))
+                                    .with_help("only properties of objects can be accessed with `.`")
+                            )))
+                        };
+                        Ok(ValueOrStr::Value(value[std::str::from_utf8(call_expression.name).unwrap()].clone()))
+                    }
+                    Expression::Index(index_expression) => {
+                        let object = match self.execute(&index_expression.object)? {
+                            range @ ValueOrStr::Range(_) => ValueOrStr::Value(Self::to_value(range)),
+                            object => object
+                        };
+                        let index = self.execute(&index_expression.index)?;
+                        Self::index(object, index, index_expression.span)
                     }
                     Expression::Variable(variable_expression) => {
+                        if let Some(level) = self.loop_level(statement) {
+                            return Ok(ValueOrStr::Value(self.loop_value(level)));
+                        }
                         // the value from context_scope
-                        self.get(variable_expression.name)
+                        Ok(self.get(variable_expression.name))
                     }
                     Expression::Literal(literal_expression) => {
                         match literal_expression.token.token_type {
                             TokenType::String => {
                                 let value = literal_expression.token.token_value;
-                                ValueOrStr::Str(&value[1 .. value.len() - 1])
+                                Ok(ValueOrStr::Str(&value[1 .. value.len() - 1]))
+                            }
+                            TokenType::Number => {
+                                let text = String::from_utf8_lossy(literal_expression.token.token_value);
+                                match Self::parse_number(&text) {
+                                    Some(number) => Ok(ValueOrStr::Value(Value::Number(number))),
+                                    None => Err(RenderError::Runtime(Box::new(
+                                        Diagnostic::new("E0206", "Number literal out of range", literal_expression.span)
+                                            .with_label("not a finite number")
+                                    )))
+                                }
+                            }
+                            TokenType::True => Ok(ValueOrStr::Value(Value::Bool(true))),
+                            TokenType::False => Ok(ValueOrStr::Value(Value::Bool(false))),
+                            TokenType::Null => Ok(ValueOrStr::Value(Value::Null)),
+                            _ => Err(RenderError::Runtime(Box::new(Diagnostic::new(
+                                "E0204",
+                                format!("Unsupported literal {}", literal_expression.token.token_type.description()),
+                                literal_expression.span
+                            ))))
+                        }
+                    }
+                    Expression::Filter(filter_expression) => {
+                        let value = Self::to_value(self.execute(&filter_expression.value)?);
+                        let arguments = self.evaluate_arguments(&filter_expression.arguments)?;
+                        let name = String::from_utf8_lossy(filter_expression.name.token_value);
+                        let filter = match self.filters.get(name.as_ref()) {
+                            Some(filter) => filter,
+                            None => return Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new("E0211", format!("Unknown filter `{}`", name), filter_expression.name.span)
+                                    .with_label("no filter with this name")
+                            )))
+                        };
+                        match filter(value, &arguments) {
+                            Ok(value) => Ok(ValueOrStr::Value(value)),
+                            Err(error) => Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new("E0212", error.message, filter_expression.span)
+                                    .with_label(format!("in filter `{}`", name))
+                            )))
+                        }
+                    }
+                    Expression::Test(test_expression) => {
+                        let value = Self::to_value(self.execute(&test_expression.value)?);
+                        let arguments = self.evaluate_arguments(&test_expression.arguments)?;
+                        let name = String::from_utf8_lossy(test_expression.name.token_value);
+                        let test = match self.tests.get(name.as_ref()) {
+                            Some(test) => test,
+                            None => return Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new("E0213", format!("Unknown test `{}`", name), test_expression.name.span)
+                                    .with_label("no test with this name")
+                            )))
+                        };
+                        match test(&value, &arguments) {
+                            Ok(result) => Ok(ValueOrStr::Value(Value::Bool(result != test_expression.negated))),
+                            Err(error) => Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new("E0214", error.message, test_expression.span)
+                                    .with_label(format!("in test `{}`", name))
+                            )))
+                        }
+                    }
+                    Expression::FunctionCall(function_call_expression) => {
+                        let name = String::from_utf8_lossy(function_call_expression.name.token_value);
+                        let receiver = match &function_call_expression.receiver {
+                            Some(receiver) => Some(Self::to_value(self.execute(receiver)?)),
+                            None => None
+                        };
+                        let mut arguments = self.evaluate_arguments(&function_call_expression.arguments)?;
+                        // `range` is built in so it can be iterated lazily, unless replaced by a registered function
+                        if receiver.is_none() && name == "range" && !self.functions.contains_key("range") {
+                            return self.range_function(&arguments, function_call_expression.span);
+                        }
+                        if let Some(receiver) = receiver {
+                            // methods are filters applied to the receiver, else functions receiving it first
+                            if let Some(filter) = self.filters.get(name.as_ref()) {
+                                return filter(receiver, &arguments).map(ValueOrStr::Value).map_err(|error| RenderError::Runtime(Box::new(
+                                    Diagnostic::new("E0212", error.message, function_call_expression.span)
+                                        .with_label(format!("in filter `{}`", name))
+                                )));
                             }
-                            _ => todo!(),
+                            arguments.positional.insert(0, receiver);
                         }
+                        let function = match self.functions.get(name.as_ref()) {
+                            Some(function) => function,
+                            None => return Err(RenderError::Runtime(Box::new(
+                                Diagnostic::new("E0215", format!("Unknown function `{}`", name), function_call_expression.name.span)
+                                    .with_label("no function with this name")
+                            )))
+                        };
+                        function(&arguments).map(ValueOrStr::Value).map_err(|error| RenderError::Runtime(Box::new(
+                            Diagnostic::new("E0216", error.message, function_call_expression.span)
+                                .with_label(format!("in function `{}`", name))
+                        )))
                     }
                     Expression::TemplateLiteral(template_literal_expression) => {
                         // just the template literal
-                        return ValueOrStr::Str(template_literal_expression.value);
+                        Ok(ValueOrStr::Str(template_literal_expression.value))
                     }
                 }
             }
-            Statement::For(for_statement) => {
-                let value_or_str = self.execute(&for_statement.array_variable);
-                let array;
-                if let ValueOrStr::Value(i) = value_or_str {
-                    array = i;
-                } else {
-                    // only array can be used with for loop
-                    panic!("Not array");
-                }
-                if !array.is_array() {
-                    panic!("Not array");
+            Statement::For(for_statement) => self.execute_for(for_statement),
+            Statement::Set(set_statement) => {
+                let value = match &set_statement.value {
+                    Some(value) => Self::to_value(self.execute(value)?),
+                    None => Value::String(self.interpret(&set_statement.statements)?)
+                };
+                // top frame is the scope pushed for the enclosing block
+                if let Some(Value::Object(scope)) = self.context_stack.borrow_mut().last_mut() {
+                    scope.insert(String::from_utf8_lossy(set_statement.name).into_owned(), value);
                 }
-                let mut result = String::new();
-                for i in array.as_array().unwrap() {
-                    // add current array value to context_scope
-                    self.context_stack.borrow_mut().push(serde_json::json!({std::str::from_utf8(for_statement.instance_identifier).unwrap(): i}));
-                    // interpret the block for each element in array
-                    result.push_str(&self.interpret(&for_statement.statements));
-                    self.context_stack.borrow_mut().pop();
+                Ok(ValueOrStr::Str(b""))
+            }
+            Statement::With(with_statement) => {
+                let value = Self::to_value(self.execute(&with_statement.value)?);
+                let scope = match (with_statement.alias, value) {
+                    (Some(alias), value) => {
+                        let mut scope = Map::new();
+                        scope.insert(String::from_utf8_lossy(alias).into_owned(), value);
+                        Value::Object(scope)
+                    }
+                    (None, value @ Value::Object(_)) => value,
+                    // only objects can be used as scope
+                    (None, value) => return Err(RenderError::Runtime(Box::new(
+                        Diagnostic::new("E0220", format!("Cannot use {} as scope", filter::type_name(&value)), with_statement.value.span())
+                            .with_label("expected object")
+                            .with_help("use `with value as name` to bind other values to a name")
+                    )))
+                };
+                self.context_stack.borrow_mut().push(scope);
+                let block = self.interpret(&with_statement.statements);
+                self.context_stack.borrow_mut().pop();
+                Ok(ValueOrStr::Value(Value::String(block?)))
+            }
+            Statement::Break(_) => {
+                self.loop_control.set(Some(LoopControl::Break));
+                Ok(ValueOrStr::Str(b""))
+            }
+            Statement::Continue(_) => {
+                self.loop_control.set(Some(LoopControl::Continue));
+                Ok(ValueOrStr::Str(b""))
+            }
+            Statement::When(when_statement) => {
+                let subject = self.execute(&when_statement.subject)?;
+                for arm in &when_statement.arms {
+                    for value in &arm.values {
+                        if Self::is_equals(&subject, &self.execute(value)?) {
+                            return Ok(ValueOrStr::Value(Value::String(self.interpret(&arm.statements)?)));
+                        }
+                    }
                 }
-                return ValueOrStr::Value(serde_json::Value::String(result));
+                Ok(ValueOrStr::Value(Value::String(self.interpret(&when_statement.else_statements)?)))
             }
             Statement::If(if_statement) => {
-                if Self::is_truthy(self.execute(&if_statement.condition)) {
-                    ValueOrStr::Value(serde_json::Value::String(self.interpret(&if_statement.if_statements)))
-                } else if if_statement.else_statements.len() != 0 {
-                    ValueOrStr::Value(serde_json::Value::String(self.interpret(&if_statement.else_statements)))
+                for branch in &if_statement.branches {
+                    if Self::is_truthy(self.execute(&branch.condition)?) {
+                        return Ok(ValueOrStr::Value(serde_json::Value::String(self.interpret(&branch.statements)?)));
+                    }
+                }
+                if !if_statement.else_statements.is_empty() {
+                    Ok(ValueOrStr::Value(serde_json::Value::String(self.interpret(&if_statement.else_statements)?)))
                 } else {
-                    ValueOrStr::Value(serde_json::Value::String(String::from("")))
+                    Ok(ValueOrStr::Value(serde_json::Value::String(String::from(""))))
                 }
             },
         }
     }
 
+    /// Returns a ValueOrStr enum that is a result of executing for statement, the block is
+    /// interpreted for every item of the iterable passing the optional condition, or the else
+    /// block if there are none
+    /// 
+    /// # Arguments
+    /// 
+    /// * `for_statement` - An Abstract Syntax Tree (AST) that represents a for statement
+    fn execute_for(&self, for_statement: &'a ForStatement) -> Result<ValueOrStr<'a>, RenderError> {
+        let key_identifier = for_statement.key_identifier.map(|key| String::from_utf8_lossy(key).into_owned());
+        let identifier = String::from_utf8_lossy(for_statement.instance_identifier).into_owned();
+        // pairs of index or key and item, a single identifier binds keys of objects
+        let (mut entries, mut length): (Box<dyn Iterator<Item = (Value, Value)>>, usize) = match self.execute(&for_statement.array_variable)? {
+            ValueOrStr::Value(Value::Array(array)) => {
+                let length = array.len();
+                (Box::new(array.into_iter().enumerate().map(|(index, item)| (Value::from(index), item))), length)
+            }
+            ValueOrStr::Value(Value::Object(object)) => {
+                let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+                if self.options.sorted_objects {
+                    entries.sort_by(|left, right| left.0.cmp(&right.0));
+                }
+                let length = entries.len();
+                match for_statement.key_identifier {
+                    Some(_) => (Box::new(entries.into_iter().map(|(key, value)| (Value::String(key), value))), length),
+                    None => (Box::new(entries.into_iter().map(|(key, _)| (Value::Null, Value::String(key)))), length)
+                }
+            }
+            ValueOrStr::Range(range) => {
+                (Box::new(range.iter().enumerate().map(|(index, item)| (Value::from(index), Value::from(item)))), range.length)
+            }
+            ValueOrStr::Value(Value::Null) if self.options.null_is_empty => (Box::new(std::iter::empty()), 0),
+            // only arrays, objects and ranges can be used with for loop
+            value => return Err(RenderError::Runtime(Box::new(
+                Diagnostic::new("E0201", "Not iterable", for_statement.array_variable.span())
+                    .with_label(format!("found {}", Self::type_name(&value)))
+                    .with_help("only arrays, objects and ranges can be iterated with `for`")
+            )))
+        };
+        if let Some(condition) = &for_statement.condition {
+            let mut filtered = Vec::new();
+            for (key, item) in entries {
+                self.context_stack.borrow_mut().push(Self::bind(key_identifier.as_deref(), &identifier, key, item));
+                let passes = self.execute(condition).map(Self::is_truthy);
+                let scope = self.context_stack.borrow_mut().pop();
+                let (key, item) = Self::unbind(scope, key_identifier.as_deref(), &identifier);
+                if passes? {
+                    filtered.push((key, item));
+                }
+            }
+            length = filtered.len();
+            entries = Box::new(filtered.into_iter());
+        }
+        if length == 0 {
+            return Ok(ValueOrStr::Value(Value::String(self.interpret(&for_statement.else_statements)?)));
+        }
+        let mut previous = Value::Null;
+        let mut items = entries.peekable();
+        let mut result = String::new();
+        let mut index = 0;
+        while let Some((key, item)) = items.next() {
+            // only the next item is cloned, previous item is moved out of the popped scope
+            let next = items.peek().map_or(Value::Null, |(_, next)| next.clone());
+            let scope_depth = self.context_stack.borrow().len();
+            // add current array value to context_scope and loop state for `loop`
+            self.context_stack.borrow_mut().push(Self::bind(key_identifier.as_deref(), &identifier, key, item));
+            self.loop_stack.borrow_mut().push(LoopState { index, length, previous, next, scope_depth });
+            // interpret the block for each element in array
+            let block = self.interpret(&for_statement.statements);
+            self.loop_stack.borrow_mut().pop();
+            let scope = self.context_stack.borrow_mut().pop();
+            result.push_str(&block?);
+            previous = Self::unbind(scope, key_identifier.as_deref(), &identifier).1;
+            index += 1;
+            if self.loop_control.take() == Some(LoopControl::Break) {
+                break;
+            }
+        }
+        Ok(ValueOrStr::Value(serde_json::Value::String(result)))
+    }
+
+    /// Returns range from `start` to `end` excluding `end` in steps of `step`, errors if it has
+    /// more integers than allowed by options
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - first integer
+    /// * `end` - integer the range stops before, wider than `i64` so `..=` can include `i64::MAX`
+    /// * `step` - difference between consecutive integers, not zero
+    /// * `span` - location of the range, used for errors
+    fn range(&self, start: i64, end: i128, step: i64, span: Span) -> Result<ValueOrStr<'a>, RenderError> {
+        let (start_wide, step_wide) = (i128::from(start), i128::from(step));
+        let length = if step > 0 {
+            (end - start_wide + step_wide - 1).div_euclid(step_wide)
+        } else {
+            (start_wide - end - step_wide - 1).div_euclid(-step_wide)
+        }.max(0);
+        if length > self.options.max_range_length as i128 {
+            return Err(RenderError::Runtime(Box::new(
+                Diagnostic::new(
+                    "E0218",
+                    format!("Range of {} integers exceeds the limit of {}", length, self.options.max_range_length),
+                    span
+                )
+                    .with_label("range is too long")
+                    .with_help("raise `max_range_length` in `Options` to allow longer ranges")
+            )));
+        }
+        Ok(ValueOrStr::Range(Range { start, step, length: length as usize }))
+    }
+
+    /// Returns range created by `range(end)`, `range(start, end)` or `range(start, end, step)`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `arguments` - arguments of the call
+    /// * `span` - location of the call, used for errors
+    fn range_function(&self, arguments: &Arguments, span: Span) -> Result<ValueOrStr<'a>, RenderError> {
+        let integers: Option<Vec<i64>> = arguments.positional.iter().map(Value::as_i64).collect();
+        let error = |message: &str| Err(RenderError::Runtime(Box::new(
+            Diagnostic::new("E0219", message, span).with_label("in function `range`")
+        )));
+        let (start, end, step) = match integers.as_deref() {
+            Some([end]) if arguments.named.is_empty() => (0, *end, 1),
+            Some([start, end]) if arguments.named.is_empty() => (*start, *end, 1),
+            Some([start, end, step]) if arguments.named.is_empty() => (*start, *end, *step),
+            _ => return error("`range` expects 1 to 3 integer arguments")
+        };
+        if step == 0 {
+            return error("Range step cannot be zero");
+        }
+        self.range(start, i128::from(end), step, span)
+    }
+
+    /// Returns scope of a for loop iteration binding `item` to `identifier`, and `key` to
+    /// `key_identifier` if given
+    /// 
+    /// # Arguments
+    /// 
+    /// * `key_identifier` - name bound to index or key
+    /// * `identifier` - name bound to item
+    /// * `key` - index or key of item
+    /// * `item` - the current item
+    fn bind(key_identifier: Option<&str>, identifier: &str, key: Value, item: Value) -> Value {
+        let mut scope = Map::new();
+        if let Some(key_identifier) = key_identifier {
+            scope.insert(key_identifier.to_string(), key);
+        }
+        scope.insert(identifier.to_string(), item);
+        Value::Object(scope)
+    }
+
+    /// Returns key and item moved out of `scope` created by `bind`, null if missing
+    /// 
+    /// # Arguments
+    /// 
+    /// * `scope` - the popped scope
+    /// * `key_identifier` - name bound to index or key
+    /// * `identifier` - name bound to item
+    fn unbind(scope: Option<Value>, key_identifier: Option<&str>, identifier: &str) -> (Value, Value) {
+        match scope {
+            Some(Value::Object(mut scope)) => (
+                key_identifier.and_then(|key_identifier| scope.remove(key_identifier)).unwrap_or(Value::Null),
+                scope.remove(identifier).unwrap_or(Value::Null)
+            ),
+            _ => (Value::Null, Value::Null)
+        }
+    }
+
+    /// Returns position in loop_stack of the innermost for loop if `loop` refers to it, i.e. no
+    /// scope inside the loop defines `loop`
+    fn current_loop(&self) -> Option<usize> {
+        let loop_stack = self.loop_stack.borrow();
+        let state = loop_stack.last()?;
+        let shadowed = self.context_stack.borrow()[state.scope_depth + 1 ..]
+            .iter()
+            .any(|scope| scope.get("loop").is_some());
+        (!shadowed).then(|| loop_stack.len() - 1)
+    }
+
+    /// Returns position in loop_stack of the for loop `statement` refers to if it is `loop`
+    /// followed by any number of `.parent`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `statement` - the statement to check
+    fn loop_level(&self, statement: &Statement) -> Option<usize> {
+        match statement {
+            Statement::Expression(Expression::Variable(variable_expression)) if variable_expression.name == b"loop" => {
+                self.current_loop()
+            }
+            Statement::Expression(Expression::Call(call_expression)) if call_expression.name == b"parent" => {
+                self.loop_level(&call_expression.callee)?.checked_sub(1)
+            }
+            _ => None
+        }
+    }
+
+    /// Returns property `name` of `loop` of for loop at `level` in loop_stack, null if unknown
+    /// 
+    /// # Arguments
+    /// 
+    /// * `level` - position of the for loop in loop_stack
+    /// * `name` - name of the property
+    fn loop_property(&self, level: usize, name: &[u8]) -> Value {
+        let loop_stack = self.loop_stack.borrow();
+        let state = &loop_stack[level];
+        match name {
+            b"index" => Value::from(state.index + 1),
+            b"index0" => Value::from(state.index),
+            b"revindex" => Value::from(state.length - state.index),
+            b"revindex0" => Value::from(state.length - state.index - 1),
+            b"first" => Value::Bool(state.index == 0),
+            b"last" => Value::Bool(state.index + 1 == state.length),
+            b"length" => Value::from(state.length),
+            b"previous" => state.previous.clone(),
+            b"next" => state.next.clone(),
+            b"parent" if level > 0 => {
+                drop(loop_stack);
+                self.loop_value(level - 1)
+            }
+            _ => Value::Null
+        }
+    }
+
+    /// Returns `loop` of for loop at `level` in loop_stack as an object, with its parents
+    /// 
+    /// # Arguments
+    /// 
+    /// * `level` - position of the for loop in loop_stack
+    fn loop_value(&self, level: usize) -> Value {
+        const PROPERTIES: [&str; 10] = ["index", "index0", "revindex", "revindex0", "first", "last", "length", "previous", "next", "parent"];
+        Value::Object(PROPERTIES
+            .iter()
+            .map(|name| (name.to_string(), self.loop_property(level, name.as_bytes())))
+            .collect())
+    }
+
     /// Returns true if given value is truthy else falsy
     /// Value::Null, Value::Number(0), Value::String(""), Value::Array(array) of len 0, Value::Bool(false), 
     /// str of length 0 are falsy, all other ValueOrStr are truthy
@@ -191,82 +760,605 @@ impl<'a> Interperter {
     /// * `value_or_str` - the value to evaluate
     fn is_truthy(value_or_str: ValueOrStr) -> bool {
         match value_or_str {
-            ValueOrStr::Value(value) => {
-                match value {
-                    Value::Null => false,
-                    Value::Bool(boolean) => boolean,
-                    Value::Number(number) => if number.as_f64().unwrap() != 0.0 {true} else {false},
-                    Value::String(string) => {
-                        if string.len() != 0 {true} else {false}
-                    }
-                    Value::Array(array) => if array.len() != 0 {true} else {false},
-                    Value::Object(_) => true,
-                }
+            ValueOrStr::Value(value) => filter::is_truthy(&value),
+            ValueOrStr::Str(str) => !str.is_empty(),
+            ValueOrStr::Range(range) => range.length > 0
+        }
+    }
+
+    /// Returns name of the type of `value_or_str` used in diagnostics
+    /// 
+    /// # Arguments
+    /// 
+    /// * `value_or_str` - the value to get type name of
+    fn type_name(value_or_str: &ValueOrStr) -> &'static str {
+        match value_or_str {
+            ValueOrStr::Value(value) => filter::type_name(value),
+            ValueOrStr::Str(_) => "string",
+            ValueOrStr::Range(_) => "range"
+        }
+    }
+
+    /// Returns bytes of `value_or_str` if it is a string, else `Option::None`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `value_or_str` - the value to get bytes of
+    fn as_bytes<'b>(value_or_str: &'b ValueOrStr) -> Option<&'b [u8]> {
+        match value_or_str {
+            ValueOrStr::Str(string) => Some(string),
+            ValueOrStr::Value(Value::String(string)) => Some(string.as_bytes()),
+            _ => None
+        }
+    }
+
+    /// Return true if `left` and `right` are equal, strings are compared byte by byte, numbers are
+    /// compared by value regardless of representation, values of different types are never equal
+    /// 
+    /// # Arguments
+    /// 
+    /// * `left` - left side of comparison
+    /// * `right` - right side of comparison
+    fn is_equals(left: &ValueOrStr, right: &ValueOrStr) -> bool {
+        if let (Some(left), Some(right)) = (Self::as_bytes(left), Self::as_bytes(right)) {
+            return left == right;
+        }
+        match (left, right) {
+            (ValueOrStr::Value(left), ValueOrStr::Value(right)) => filter::is_equal(left, right),
+            _ => false
+        }
+    }
+
+    /// Returns ordering of `left` relative to `right`, numbers are ordered by value and strings
+    /// lexicographically, values of other or different types are an error if comparisons are
+    /// strict, else `Option::None`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `left` - left side of comparison
+    /// * `right` - right side of comparison
+    /// * `span` - location of the comparison, used for errors
+    fn compare(&self, left: &ValueOrStr, right: &ValueOrStr, span: Span) -> Result<Option<Ordering>, RenderError> {
+        if let (Some(left), Some(right)) = (Self::as_bytes(left), Self::as_bytes(right)) {
+            return Ok(Some(left.cmp(right)));
+        }
+        if let (ValueOrStr::Value(Value::Number(left)), ValueOrStr::Value(Value::Number(right))) = (left, right) {
+            return Ok(match (left.as_i64(), right.as_i64()) {
+                (Some(left), Some(right)) => Some(left.cmp(&right)),
+                _ => left.as_f64().partial_cmp(&right.as_f64())
+            });
+        }
+        if !self.options.strict_comparisons {
+            return Ok(None);
+        }
+        Err(RenderError::Runtime(Box::new(
+            Diagnostic::new("E0207", format!("Cannot compare {} with {}", Self::type_name(left), Self::type_name(right)), span)
+                .with_label("only numbers and strings can be ordered")
+                .with_help("set `strict_comparisons` to false to treat this comparison as false")
+        )))
+    }
+
+    /// Returns element of `object` at `index`, arrays and strings are indexed by integers counting
+    /// from the end if negative, objects are indexed by keys, missing elements are null
+    /// 
+    /// # Arguments
+    /// 
+    /// * `object` - the value being indexed
+    /// * `index` - the index or key
+    /// * `span` - location of the index expression, used for errors
+    fn index(object: ValueOrStr<'a>, index: ValueOrStr<'a>, span: Span) -> Result<ValueOrStr<'a>, RenderError> {
+        let position = |length: usize, index: i64| {
+            let index = if index < 0 { index + length as i64 } else { index };
+            usize::try_from(index).ok().filter(|index| *index < length)
+        };
+        let integer = match &index {
+            ValueOrStr::Value(Value::Number(number)) => number.as_i64(),
+            _ => None
+        };
+        match (object, integer) {
+            (ValueOrStr::Value(Value::Array(mut array)), Some(integer)) => {
+                Ok(ValueOrStr::Value(position(array.len(), integer).map_or(Value::Null, |i| array.swap_remove(i))))
+            }
+            (ValueOrStr::Value(Value::Object(mut object)), None) if Self::as_bytes(&index).is_some() => {
+                let key = String::from_utf8_lossy(Self::as_bytes(&index).unwrap());
+                Ok(ValueOrStr::Value(object.remove(key.as_ref()).unwrap_or(Value::Null)))
             }
-            ValueOrStr::Str(str) => {
-                if str.len() != 0 {true} else {false}
+            (object, Some(integer)) if Self::as_bytes(&object).is_some() => {
+                let string = String::from_utf8_lossy(Self::as_bytes(&object).unwrap()).into_owned();
+                let characters: Vec<char> = string.chars().collect();
+                Ok(ValueOrStr::Value(position(characters.len(), integer)
+                    .map_or(Value::Null, |i| Value::String(characters[i].to_string()))))
             }
+            (object, _) => Err(RenderError::Runtime(Box::new(
+                Diagnostic::new("E0217", format!("Cannot index {} with {}", Self::type_name(&object), Self::type_name(&index)), span)
+                    .with_label("expected array or string with integer, or object with string")
+            )))
         }
     }
 
-    /// Return true if `left` and `right` are equal
-    /// implementation unstable (to be changed)
-    fn is_equals(left: &ValueOrStr, right: &ValueOrStr) -> bool {
-        let left_string = match left {
-            ValueOrStr::Str(string) => string,
-            ValueOrStr::Value(value) => {
-                match value {
-                    Value::String(string) => string.as_bytes(),
-                    _ => todo!(),
-                }
+    /// Returns values of `argument_list` evaluated in order
+    /// 
+    /// # Arguments
+    /// 
+    /// * `argument_list` - the arguments to evaluate
+    fn evaluate_arguments(&self, argument_list: &'a ArgumentList) -> Result<Arguments, RenderError> {
+        let mut arguments = Arguments::default();
+        for argument in &argument_list.positional {
+            arguments.positional.push(Self::to_value(self.execute(argument)?));
+        }
+        for (name, argument) in &argument_list.named {
+            arguments.named.push((String::from_utf8_lossy(name).into_owned(), Self::to_value(self.execute(argument)?)));
+        }
+        Ok(arguments)
+    }
+
+    /// Returns result of arithmetic `binary_expression`, integers stay integers unless the result
+    /// overflows, `/` of integers rounds toward zero like `%`, `+` concatenates strings
+    /// 
+    /// # Arguments
+    /// 
+    /// * `left` - left operand
+    /// * `right` - right operand
+    /// * `binary_expression` - the expression being evaluated, used for operator and errors
+    fn arithmetic(left: &ValueOrStr, right: &ValueOrStr, binary_expression: &BinaryExpression) -> Result<ValueOrStr<'a>, RenderError> {
+        let operator = binary_expression.operator.token_type;
+        if operator == TokenType::Plus {
+            if let (Some(left), Some(right)) = (Self::as_bytes(left), Self::as_bytes(right)) {
+                return Ok(ValueOrStr::Value(Value::String(format!(
+                    "{}{}",
+                    String::from_utf8_lossy(left),
+                    String::from_utf8_lossy(right)
+                ))));
             }
+        }
+        let (left_number, right_number) = match (left, right) {
+            (ValueOrStr::Value(Value::Number(left)), ValueOrStr::Value(Value::Number(right))) => (left, right),
+            _ => return Err(RenderError::Runtime(Box::new(
+                Diagnostic::new(
+                    "E0208",
+                    format!("Cannot apply {} to {} and {}", operator.description(), Self::type_name(left), Self::type_name(right)),
+                    binary_expression.span
+                )
+                    .with_label("expected numbers")
+                    .with_help("only strings can be concatenated with `+`")
+            )))
         };
-        let right_string = match right {
-            ValueOrStr::Str(string) => string,
-            ValueOrStr::Value(value) => {
-                match value {
-                    Value::String(string) => string.as_bytes(),
-                    _ => todo!(),
-                }
+        let is_zero = right_number.as_i64() == Some(0) || right_number.as_f64() == Some(0.0);
+        if is_zero && (operator == TokenType::Slash || operator == TokenType::Percent) {
+            return Err(RenderError::Runtime(Box::new(
+                Diagnostic::new("E0209", "Division by zero", binary_expression.right.span())
+                    .with_label("this is zero")
+            )));
+        }
+        if let (Some(left), Some(right)) = (left_number.as_i64(), right_number.as_i64()) {
+            let result = match operator {
+                TokenType::Plus => left.checked_add(right),
+                TokenType::Minus => left.checked_sub(right),
+                TokenType::Star => left.checked_mul(right),
+                TokenType::Slash => left.checked_div(right),
+                _ => left.checked_rem(right)
+            };
+            if let Some(result) = result {
+                return Ok(ValueOrStr::Value(Value::Number(Number::from(result))));
             }
+        }
+        let (left, right) = (left_number.as_f64().unwrap_or(f64::NAN), right_number.as_f64().unwrap_or(f64::NAN));
+        let result = match operator {
+            TokenType::Plus => left + right,
+            TokenType::Minus => left - right,
+            TokenType::Star => left * right,
+            TokenType::Slash => left / right,
+            _ => left % right
         };
-        if left_string == right_string {
-            return true;
+        match Number::from_f64(result) {
+            Some(number) => Ok(ValueOrStr::Value(Value::Number(number))),
+            None => Err(RenderError::Runtime(Box::new(
+                Diagnostic::new("E0210", "Arithmetic result out of range", binary_expression.span)
+                    .with_label("not a finite number")
+            )))
+        }
+    }
+
+    /// Returns number represented by `text`, integers are kept as integers if they fit in 64 bits,
+    /// `Option::None` if `text` is not a finite number
+    /// 
+    /// # Arguments
+    /// 
+    /// * `text` - the number literal, e.g. `42`, `1.5e3`
+    fn parse_number(text: &str) -> Option<Number> {
+        if !text.contains(['.', 'e', 'E']) {
+            if let Ok(integer) = text.parse::<i64>() {
+                return Some(Number::from(integer));
+            }
+            if let Ok(integer) = text.parse::<u64>() {
+                return Some(Number::from(integer));
+            }
+        }
+        text.parse::<f64>().ok().and_then(Number::from_f64)
+    }
+
+    /// Returns `number` negated, integers stay integers unless they overflow, `9223372036854775808`
+    /// is only an integer when negated, as `i64::MIN`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `number` - the number to negate
+    fn negate(number: &Number) -> Option<Number> {
+        if let Some(negated) = number.as_i64().and_then(i64::checked_neg) {
+            return Some(Number::from(negated));
         }
-        false
+        if number.as_u64() == Some(i64::MIN.unsigned_abs()) {
+            return Some(Number::from(i64::MIN));
+        }
+        Number::from_f64(-number.as_f64()?)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{parser::Parser, tokenizer::{Tokenizer}};
+    use serde_json::{json, Value};
 
-    use super::*;
+    use super::Interperter;
+    use crate::filter;
+    use crate::{render, render_file, error::RenderError, render_with_options, render_with_interperter, Options, Span, tokenizer::Tokenizer, parser::Parser, filter::Arguments, error::CallError};
 
     #[test]
     fn it_works() {
-//         let source = r#"
-// Yoo {{ for i in items }} name: {{ i.name }} {{ end }} | king: {{ person.name }}
-//         "#.as_bytes();
         let source = r#"
-Yoo {{ "here" }} {{ if "yea" && items && fds }}true{{ else }}false{{ end }}
-        "#.as_bytes();
-        let binding = Tokenizer::new(source);
-        let tokens = binding.tokenize();
-        // for token in &tokens {
-        //     println!("Token: {:?}", token);
-        // }
-        let binding = Parser::new(&tokens);
-        let statements = binding.parse();
-        let value: Value = serde_json::from_str(r#"
+Yoo {{ "here" }} {{ if "yea" && items && fds }}true{{ else }}false{{ end }} {{ for i in items }}{{ i.name }} {{ end }}"#;
+        let context = r#"
 {"items": [{"name": "John"}, {"name": "Bob"}, {"name": "Chris"}], "person": {"name": "bob"}}
-        "#).unwrap();
-//         let value: Value = serde_json::from_str(r#"
-// { "name": {"first": "Punit" } }
-//          "#).unwrap();
-        //println!("{}", value["name"]);
-        let interperter = Interperter::new(value);
-        println!("{}", interperter.interpret(&statements));
-        assert!(false);
-    }
-}
\ No newline at end of file
+        "#;
+        assert_eq!(render(source, context).unwrap(), "\nYoo here false John Bob Chris ");
+    }
+
+    #[test]
+    fn else_if() {
+        let source = r#"{{ for i in items }}{{ if i == "a" }}A{{ else if i == "b" }}B{{ elif i == "c" }}C{{ else }}?{{ end }}{{ end }}"#;
+        let context = r#"{"items": ["c", "a", "d", "b"]}"#;
+        assert_eq!(render(source, context).unwrap(), "CA?B");
+    }
+
+    #[test]
+    fn literals() {
+        let source = r#"{{ 42 }} {{ -1.5 }} {{ 2e3 }} {{ true }} {{ null }} {{ if count == 0 }}zero{{ end }} {{ if flag == true }}flag{{ end }} {{ if missing == null }}null{{ end }} {{ if price == 2 }}int{{ end }} {{ if count == "0" }}string{{ end }}"#;
+        let context = r#"{"count": 0, "flag": true, "price": 2.0}"#;
+        assert_eq!(render(source, context).unwrap(), "42 -1.5 2000 true null zero flag null int ");
+
+        let source = "{{ -9223372036854775808 }} {{ range(-9223372036854775808, -9223372036854775806) }} {{ -9223372036854775809 }}";
+        assert_eq!(render(source, "{}").unwrap(), "-9223372036854775808 [-9223372036854775808,-9223372036854775807] -9223372036854776000");
+    }
+
+    #[test]
+    fn comparisons() {
+        let source = r#"{{ for i in items }}{{ if i < 5 }}<{{ end }}{{ if i <= 5 }}<={{ end }}{{ if i > 5 }}>{{ end }}{{ if i >= 5 }}>={{ end }} {{ end }}{{ "a" < "b" }} {{ 1.5 < 2 }}"#;
+        let context = r#"{"items": [4, 5, 5.5]}"#;
+        assert_eq!(render(source, context).unwrap(), "<<= <=>= >>= true true");
+
+        let source = r#"{{ if "5" < 6 }}true{{ else }}false{{ end }}"#;
+        assert_eq!(render(source, "{}").unwrap_err().message(), "Cannot compare string with number");
+        let options = Options { strict_comparisons: false, ..Options::default() };
+        assert_eq!(render_with_options(source, "{}", &options).unwrap(), "false");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let source = r#"{{ price * quantity }} {{ index + 1 }} {{ total / count }} {{ 7 / 2 }} {{ -7 / 2 }} {{ 7.0 / 2 }} {{ 7 % 3 }} {{ 1 + 2 * 3 - -4 }} {{ 2.5 * 2 }} {{ 9223372036854775807 + 1 }} {{ "a" + name }}"#;
+        let context = r#"{"price": 3, "quantity": 4, "index": 0, "total": 10, "count": 5, "name": "b"}"#;
+        assert_eq!(render(source, context).unwrap(), "12 1 2 3 -3 3.5 1 11 5 9223372036854776000 ab");
+
+        let error = render("{{ 1 / zero }}", r#"{"zero": 0.0}"#).unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Division by zero", 8));
+        let error = render(r#"{{ "a" * 2 }}"#, "{}").unwrap_err();
+        assert_eq!(error.message(), "Cannot apply `*` to string and number");
+    }
+
+    #[test]
+    fn parentheses() {
+        let source = r#"{{ if (a || b) && c }}yes{{ else }}no{{ end }} {{ a || b && c }} {{ (1 + 2) * 3 }} {{ -(2 - 5) }}"#;
+        assert_eq!(render(source, r#"{"a": true, "b": false, "c": false}"#).unwrap(), "no true 9 3");
+
+        let error = render("{{ (1 + 2 }}", "{}").unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Unexpected `}}`", 11));
+        assert_eq!(error.diagnostic().unwrap().label.message, "expected `)`");
+    }
+
+    #[test]
+    fn filters() {
+        let source = r#"{{ name | repeat | repeat(times=3) }} {{ "b" | repeat(2, separator="-") }} {{ items | length > 1 }} {{ name | unknown }}"#;
+        let tokens = Tokenizer::new(source.as_bytes()).tokenize().unwrap();
+        let (statements, diagnostics) = Parser::new(&tokens).parse();
+        assert!(diagnostics.is_empty());
+        let mut interperter = Interperter::new(json!({"name": "a", "items": [1, 2]}));
+        interperter.filters.insert("repeat".to_string(), Box::new(|value: Value, arguments: &Arguments| {
+            let times = arguments.get(0, "times").and_then(Value::as_u64).unwrap_or(2) as usize;
+            let separator = arguments.get(1, "separator").and_then(Value::as_str).unwrap_or("");
+            match value {
+                Value::String(string) => Ok(Value::String(vec![string; times].join(separator))),
+                _ => Err(CallError::new("expected string"))
+            }
+        }));
+        interperter.filters.insert("length".to_string(), Box::new(|value: Value, _: &Arguments| {
+            Ok(Value::from(value.as_array().map_or(0, Vec::len)))
+        }));
+        let error = interperter.interpret(&statements).unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Unknown filter `unknown`", 111));
+        assert_eq!(interperter.interpret(&statements[.. statements.len() - 1]).unwrap(), "aaaaaa b-b true ");
+
+        let error = render("{{ name | upper(1, case=true, 2) }}", "{}").unwrap_err();
+        assert_eq!(error.message(), "Positional argument after named argument");
+    }
+
+    #[test]
+    fn builtin_filters() {
+        let source = concat!(
+            r#"{{ name | upper }} {{ name | lower | capitalize }} {{ "the quick fox" | title }} {{ "  x  " | trim }} "#,
+            r#"{{ "abcdefgh" | truncate(5) }} {{ "a-b" | replace("-", "+") }} {{ "a b  c" | split | join(",") }} "#,
+            r#"{{ "a,b" | split(",") | length }} {{ items | first }} {{ items | last }} {{ items | reverse }} "#,
+            r#"{{ items | sort | unique }} {{ people | sort_by("age") | first | json }} {{ missing | default("none") }} "#,
+            r#"{{ 2.567 | round(2) }} {{ 2.5 | round }} {{ (-3) | abs }} {{ "a b&c" | urlencode }} "#,
+            r#"{{ " Hello, World! " | slugify }} {{ "one two  three" | wordcount }}"#
+        );
+        let context = r#"{"name": "jOHN", "items": [3, 1, 3, 2], "people": [{"name": "b", "age": 30}, {"name": "a", "age": 20}]}"#;
+        assert_eq!(render(source, context).unwrap(), concat!(
+            "JOHN John The Quick Fox x ab... a+b a,b,c 2 3 2 [2,3,1,3] [1,2,3] ",
+            r#"{"name":"a","age":20} none 2.57 3 3 a%20b%26c hello-world 3"#
+        ));
+
+        let error = render("{{ items | upper }}", r#"{"items": []}"#).unwrap_err();
+        assert_eq!(error.message(), "Expected string, found array");
+        assert_eq!(error.diagnostic().unwrap().label.message, "in filter `upper`");
+        let error = render("{{ items | sort }}", r#"{"items": [1, "a"]}"#).unwrap_err();
+        assert_eq!(error.message(), "Cannot compare string with number");
+
+        let source = r#"[{{ "abc" | truncate(2) }}] [{{ "abcdef" | truncate(0) }}] [{{ "abcdef" | truncate(4, end="~") }}]"#;
+        assert_eq!(render(source, "{}").unwrap(), "[..] [] [abc~]");
+        assert_eq!(render("{{ items | unique }} {{ items[0] == items[1] }}", r#"{"items": [1, 1.0, 2]}"#).unwrap(), "[1,2] true");
+    }
+
+    #[test]
+    fn register() {
+        let mut interperter = Interperter::new(json!({"price": 1250, "user": {"roles": ["admin"]}}));
+        interperter.register_filter("currency", |value, arguments| {
+            let symbol = arguments.get(0, "symbol").and_then(Value::as_str).unwrap_or("$");
+            let cents = value.as_i64().ok_or_else(|| CallError::new("Expected cents"))?;
+            Ok(Value::String(format!("{}{}.{:02}", symbol, cents / 100, cents % 100)))
+        });
+        interperter.register_test("admin", |value, _| {
+            Ok(value["roles"].as_array().is_some_and(|roles| roles.contains(&json!("admin"))))
+        });
+        let source = r#"{{ price | currency }} {{ price | currency(symbol="€") }} {{ user is admin }} {{ user is not admin }} {{ 4 is even }} {{ 9 is divisibleby(3) }}"#;
+        assert_eq!(render_with_interperter(source, &interperter).unwrap(), "$12.50 €12.50 true false true true");
+
+        let error = render_with_interperter("{{ user | currency }}", &interperter).unwrap_err();
+        assert_eq!((error.message(), error.diagnostic().unwrap().code), ("Expected cents", "E0212"));
+        let error = render_with_interperter("{{ user is staff }}", &interperter).unwrap_err();
+        assert_eq!(error.message(), "Unknown test `staff`");
+
+        interperter.set_context(json!({"price": 5, "user": {"roles": []}}));
+        assert_eq!(render_with_interperter(source, &interperter).unwrap(), "$0.05 €0.05 false true true true");
+    }
+
+    #[test]
+    fn function_calls() {
+        let mut interperter = Interperter::new(json!({"name": "ada", "items": [3, 7, 5], "user": {"name": "bob"}}));
+        interperter.register_function("greet", |arguments| {
+            let name = arguments.get(0, "name").map(filter::display).unwrap_or_default();
+            let punctuation = arguments.get(1, "punctuation").and_then(Value::as_str).unwrap_or("!");
+            Ok(Value::String(format!("Hello {}{}", name, punctuation)))
+        });
+        let source = r#"{{ greet(name) }} {{ greet(user.name, punctuation="?") }} {{ name.upper() }} {{ name.truncate(2, end="") }} {{ items.max() }} {{ min(4, 2, 8) }} {{ user.name.greet() }} {{ max(items) + 1 }}"#;
+        assert_eq!(render_with_interperter(source, &interperter).unwrap(), "Hello ada! Hello bob? ADA ad 7 2 Hello bob! 8");
+
+        let error = render_with_interperter("{{ items.shuffle() }}", &interperter).unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Unknown function `shuffle`", 10));
+        let error = render_with_interperter("{{ min() }}", &interperter).unwrap_err();
+        assert_eq!((error.message(), error.diagnostic().unwrap().code), ("Expected at least one value", "E0216"));
+    }
+
+    #[test]
+    fn subscripts() {
+        let source = r#"{{ items[0] }} {{ items[-1] }} {{ items[i + 1] }} {{ items[9] }} {{ obj["my-key"] }} {{ obj[key] }} {{ items.0 }} {{ grid.1.0 }} {{ grid[-1][-1] }} {{ "abc"[-1] }} {{ obj["a b"].c[0] }}"#;
+        let context = r#"{"items": ["a", "b", "c"], "i": 0, "key": "my-key", "obj": {"my-key": 1, "a b": {"c": [true]}}, "grid": [[1, 2], [3, 4]]}"#;
+        assert_eq!(render(source, context).unwrap(), "a c b null 1 1 a 3 4 c true");
+
+        let error = render(r#"{{ items["a"] }}"#, r#"{"items": []}"#).unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Cannot index array with string", 4));
+        let error = render("{{ items.1e3 }}", "{}").unwrap_err();
+        assert_eq!(error.message(), "Unexpected number literal");
+
+        let source = "{{ o.is }} {{ o.with }} {{ o.set.as }} {{ o.null }} {{ o.end }}";
+        let context = r#"{"o": {"is": 1, "with": 2, "set": {"as": 3}, "null": 4, "end": 5}}"#;
+        assert_eq!(render(source, context).unwrap(), "1 2 3 4 5");
+    }
+
+    #[test]
+    fn file_errors() {
+        let error = render_file("does/not/exist.html", "docs/test.json").unwrap_err();
+        assert!(matches!(&error, RenderError::Io { path, .. } if path == "does/not/exist.html"));
+        assert!(error.to_string().starts_with("IO error: does/not/exist.html: "));
+    }
+
+    #[test]
+    fn demo() {
+        let output = render(include_str!("../docs/test.html"), include_str!("../docs/test.json")).unwrap();
+        assert!(output.contains("<p>This is a test document</p>"));
+        assert!(!output.contains("Fail"));
+    }
+
+    #[test]
+    fn loop_object() {
+        let source = concat!(
+            "{{ for item in items }}{{ loop.index }}.{{ item }}{{ if !loop.last }}, {{ end }}{{ end }} ",
+            "{{ for item in items }}{{ if loop.index0 % 2 == 0 }}even{{ else }}odd{{ end }}{{ loop.revindex }}{{ loop.length }} {{ end }}",
+            "{{ for item in items }}[{{ loop.previous }}<{{ loop.next }}]{{ end }} ",
+            "{{ for row in rows }}{{ for cell in row }}{{ loop.parent.index }}{{ loop.index }}{{ loop.parent.first }} {{ end }}{{ end }}"
+        );
+        let context = r#"{"items": ["a", "b", "c"], "rows": [[1], [2]]}"#;
+        assert_eq!(
+            render(source, context).unwrap(),
+            "1.a, 2.b, 3.c even33 odd23 even13 [null<b][a<c][b<null] 11true 21false "
+        );
+
+        let source = "{{ for i in items }}{{ loop.parent }} {{ loop.index }} {{ end }}{{ loop.index }}";
+        assert_eq!(render(source, r#"{"items": [1], "loop": {"index": 99}}"#).unwrap(), "null 1 99");
+
+        let source = "{{ for i in 1..=2 }}{{ for j in 3..4 }}{{ loop | json }}{{ end }} {{ end }}{{ for i in 0..1 }}{{ set loop = 5 }}{{ loop }}{{ end }}";
+        assert_eq!(render(source, "{}").unwrap(), concat!(
+            r#"{"index":1,"index0":0,"revindex":1,"revindex0":0,"first":true,"last":true,"length":1,"previous":null,"next":null,"#,
+            r#""parent":{"index":1,"index0":0,"revindex":2,"revindex0":1,"first":true,"last":false,"length":2,"previous":null,"next":2,"parent":null}} "#,
+            r#"{"index":1,"index0":0,"revindex":1,"revindex0":0,"first":true,"last":true,"length":1,"previous":null,"next":null,"#,
+            r#""parent":{"index":2,"index0":1,"revindex":1,"revindex0":0,"first":false,"last":true,"length":2,"previous":1,"next":null,"parent":null}} 5"#
+        ));
+    }
+
+    #[test]
+    fn nested_loops_are_linear() {
+        // rows are large so copying the enclosing loop per inner iteration would take minutes
+        let row: Vec<usize> = (0 .. 10_000).collect();
+        let context = json!({"rows": [row, row, row]}).to_string();
+        let source = "{{ for row in rows }}{{ for cell in row }}{{ loop.parent.index }}{{ end }}{{ end }}";
+        let start = std::time::Instant::now();
+        assert_eq!(render(source, &context).unwrap().len(), 30_000);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5), "took {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn for_destructuring() {
+        let source = "{{ for key, value in settings }}{{ key }}={{ value }};{{ end }} {{ for key in settings }}{{ key }}{{ end }} {{ for i, item in items }}{{ i }}{{ item }}{{ end }}";
+        let context = r#"{"settings": {"theme": "dark", "lang": "en"}, "items": ["a", "b"]}"#;
+        assert_eq!(render(source, context).unwrap(), "theme=dark;lang=en; themelang 0a1b");
+        let options = Options { sorted_objects: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "lang=en;theme=dark; langtheme 0a1b");
+    }
+
+    #[test]
+    fn for_else() {
+        let source = "{{ for item in items }}{{ item }}{{ else }}No results{{ end }}";
+        assert_eq!(render(source, r#"{"items": [1, 2]}"#).unwrap(), "12");
+        assert_eq!(render(source, r#"{"items": []}"#).unwrap(), "No results");
+        assert_eq!(render(source, r#"{"items": {}}"#).unwrap(), "No results");
+        assert_eq!(render(source, "{}").unwrap(), "No results");
+        let options = Options { null_is_empty: false, ..Options::default() };
+        assert_eq!(render_with_options(source, "{}", &options).unwrap_err().message(), "Not iterable");
+    }
+
+    #[test]
+    fn loop_control() {
+        let source = concat!(
+            "{{ for i in items }}{{ if i == 3 }}{{ break }}{{ end }}{{ i }}{{ end }} ",
+            "{{ for i in items }}{{ if i % 2 == 0 }}{{ continue }}{{ else }}{{ when i }}{{ is 5 }}{{ break }}{{ end }}{{ end }}{{ i }}{{ end }} ",
+            "{{ for i in items if i > 2 }}{{ i }}{{ loop.length }}{{ end }} ",
+            "{{ for row in rows }}{{ for i in row }}{{ if i == 2 }}{{ break }}{{ end }}{{ i }}{{ end }};{{ end }} ",
+            "{{ for i in items if i > 9 }}{{ i }}{{ else }}none{{ end }}"
+        );
+        let context = r#"{"items": [1, 2, 3, 4, 5], "rows": [[1, 2, 3], [4]]}"#;
+        assert_eq!(render(source, context).unwrap(), "12 13 334353 1;4; none");
+
+        let error = render("{{ if true }}{{ break }}{{ end }}{{ for i in items }}{{ end }}{{ continue }}", "{}").unwrap_err();
+        let messages: Vec<&str> = error.diagnostics().iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+        assert_eq!(messages, ["`break` outside of loop", "`continue` outside of loop"]);
+    }
+
+    #[test]
+    fn ranges() {
+        let source = concat!(
+            "{{ for i in 1..=5 }}{{ i }}{{ end }} {{ for i in 0..3 }}{{ i }}{{ end }} {{ for i in range(0, n, 2) }}{{ i }}{{ end }} ",
+            "{{ for i in range(3) }}{{ i }}{{ end }} {{ for i in range(5, 0, -2) }}{{ i }}{{ end }} {{ for i in 3..1 }}{{ i }}{{ else }}empty{{ end }} ",
+            "{{ for i, star in 1..=3 if star <= rating }}{{ i }}*{{ end }} {{ (1..4) | join(\",\") }} {{ (1..n)[-1] }} {{ 1..3 }}"
+        );
+        assert_eq!(render(source, r#"{"n": 7, "rating": 2}"#).unwrap(), "12345 012 0246 012 531 empty 0*1* 1,2,3 6 [1,2]");
+
+        let error = render("{{ for i in 0..=10000 }}{{ end }}", "{}").unwrap_err();
+        assert_eq!(error.message(), "Range of 10001 integers exceeds the limit of 10000");
+        let options = Options { max_range_length: 3, ..Options::default() };
+        assert_eq!(render_with_options("{{ for i in 0..3 }}{{ i }}{{ end }}", "{}", &options).unwrap(), "012");
+        assert!(render_with_options("{{ for i in 0..4 }}{{ i }}{{ end }}", "{}", &options).is_err());
+        let error = render("{{ for i in range(0, 5, 0) }}{{ end }}", "{}").unwrap_err();
+        assert_eq!(error.message(), "Range step cannot be zero");
+        let error = render("{{ 1..x }}", r#"{"x": 1.5}"#).unwrap_err();
+        assert_eq!(error.message(), "Range bounds must be integers, found number and number");
+    }
+
+    #[test]
+    fn set() {
+        let source = concat!(
+            "{{ set city = user.profile.address.city }}{{ city }} {{ let total = price * 2 }}{{ total }} ",
+            "{{ if true }}{{ set city = \"inner\" }}{{ city }}{{ end }} {{ city }} ",
+            "{{ for i in 1..=2 }}{{ set doubled = i * 2 }}{{ doubled }}{{ end }}{{ doubled }} ",
+            "{{ set greeting }}Hi {{ user.name }}!{{ end }}{{ greeting | upper }}"
+        );
+        let context = r#"{"price": 5, "user": {"name": "ann", "profile": {"address": {"city": "Oslo"}}}}"#;
+        assert_eq!(render(source, context).unwrap(), "Oslo 10 inner Oslo 24null HI ANN!");
+
+        let error = render("{{ set 1 = 2 }}", "{}").unwrap_err();
+        assert_eq!(error.message(), "Unexpected number literal");
+        let error = render("{{ set x y }}a{{ end }}", "{}").unwrap_err();
+        assert_eq!((error.diagnostics().len(), error.message()), (1, "Unexpected identifier"));
+
+        let source = "{{ set s = null }}{{ s }} {{ with missing as m }}{{ m }}{{ end }}";
+        assert_eq!(render(source, r#"{"s": "outer", "m": "outer"}"#).unwrap(), "null null");
+    }
+
+    #[test]
+    fn with() {
+        let source = concat!(
+            "{{ with order.customer.billing as b }}{{ b.line1 }}, {{ b.city }}{{ end }} ",
+            "{{ with order.customer }}{{ name }} {{ billing.city }} {{ order.total }}{{ end }} {{ name }} ",
+            "{{ with order.total * 2 as doubled }}{{ doubled }}{{ end }}"
+        );
+        let context = r#"{"name": "root", "order": {"total": 3, "customer": {"name": "Ada", "billing": {"line1": "1 Main St", "city": "Oslo"}}}}"#;
+        assert_eq!(render(source, context).unwrap(), "1 Main St, Oslo Ada Oslo 3 root 6");
+
+        let error = render("{{ with order.total }}{{ end }}", context).unwrap_err();
+        assert_eq!((error.message(), error.span().unwrap().column), ("Cannot use number as scope", 9));
+    }
+
+    #[test]
+    fn when() {
+        let source = r#"{{ for i in items }}{{ when i.kind }}
+    {{ is "a" }}A{{ is "b", "c" }}BC{{ else }}?{{ end }}{{ end }}"#;
+        let context = r#"{"items": [{"kind": "a"}, {"kind": "c"}, {"kind": "d"}, {"kind": "b"}]}"#;
+        assert_eq!(render(source, context).unwrap(), "ABC?BC");
+    }
+
+    #[test]
+    fn whitespace_control() {
+        let context = r#"{"items": [1, 2]}"#;
+        let source = "<ul>\n  {{- for i in items -}}\n  <li>{{ i }}</li>\n  {{- end }}\n</ul>";
+        assert_eq!(render(source, context).unwrap(), "<ul><li>1</li><li>2</li>\n</ul>");
+        assert_eq!(render("a {{- -1 }} {{ 2 -}} b", context).unwrap(), "a-1 2b");
+
+        let source = "<ul>\n  {{ for i in items }}\n  <li>{{ i }}</li>\n  {{ end }}\n</ul> {{ if true }}x{{ end }}";
+        let options = Options { trim_blocks: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "<ul>\n    <li>1</li>\n    <li>2</li>\n  </ul> x");
+        let options = Options { trim_blocks: true, lstrip_blocks: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul> x");
+
+        assert_eq!(render("a {{- raw -}} {{ x }} {{- endraw -}} b", context).unwrap(), "a{{ x }}b");
+        assert_eq!(render("a {{-raw}}{{ x }} {{endraw-}} b", context).unwrap(), "a{{ x }} b");
+        let source = "<p>\n  {{ raw }}\n  {{ x }}\n  {{ endraw }}\n</p>";
+        let options = Options { trim_blocks: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "<p>\n    {{ x }}\n  </p>");
+        let options = Options { lstrip_blocks: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "<p>\n\n  {{ x }}\n\n</p>");
+        let options = Options { trim_blocks: true, lstrip_blocks: true, ..Options::default() };
+        assert_eq!(render_with_options(source, context, &options).unwrap(), "<p>\n  {{ x }}\n</p>");
+    }
+
+    #[test]
+    fn comments() {
+        let source = "{# header\n{{ secret }} #}{{ for i in items }}{{ i }}{# , #}{{ end }}";
+        assert_eq!(render(source, r#"{"items": [1, 2]}"#).unwrap(), "12");
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let error = render("{{ for i in person }}{{ end }}", r#"{"person": "bob"}"#).unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 12, end: 18, line: 1, column: 13 }));
+        assert_eq!(error.message(), "Not iterable");
+        let error = render("{{ person.name.first }}", r#"{"person": {"name": "bob"}}"#).unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 3, end: 20, line: 1, column: 4 }));
+        assert_eq!(error.message(), "first is undefined");
+    }
+}
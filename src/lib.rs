@@ -5,14 +5,16 @@ use serde_json::Value;
 use tokenizer::Tokenizer;
 use wasm_bindgen::prelude::*;
 
-use crate::interperter::Interperter;
+use crate::{interperter::Interperter, error::RenderError, message_formatter::FormatOptions};
 
+pub mod error;
 pub mod tokenizer;
 pub mod parser;
 pub mod message_formatter;
 pub mod statement;
 pub mod expression;
 pub mod interperter;
+pub mod filter;
 
 /*
 Grammer rules of YARTL in Extended Backus–Naur Form (EBNF)
@@ -21,54 +23,190 @@ statement = template_literal
             | '{{' expression '}}'
             | for
             | if
+            | when
+            | set
+            | with
+            | '{{' 'break' '}}'
+            | '{{' 'continue' '}}'
 expression = or
 or = and { '||' and }
 and = equality { '&&' equality }
-equality = unary {( ('!=' | '==' ) unary )}
-unary = ['!'] call 
-call = ( identifier { '.' identifier } ) | literal
-literal = string
+equality = comparison {( ('!=' | '==' ) comparison )}
+comparison = test {( ('<' | '<=' | '>' | '>=' ) test )}
+test = range [ 'is' [ 'not' ] identifier [ arguments ] ]
+range = term [ ( '..' | '..=' ) term ]
+term = factor {( ('+' | '-' ) factor )}
+factor = unary {( ('*' | '/' | '%' ) unary )}   (* `/` of two integers is an integer rounded toward zero *)
+unary = ( ( '!' | '-' ) unary ) | filter
+filter = call { '|' identifier [ arguments ] }
+arguments = '(' [ argument { ',' argument } ] ')'
+argument = [ identifier '=' ] expression
+call = ( identifier arguments | primary ) { ( '.' identifier [ arguments ] ) | ( '.' number ) | ( '[' expression ']' ) }
+primary = identifier | literal | '(' expression ')'
+literal = string | number | 'true' | 'false' | 'null'
+raw = '{{' 'raw' '}}' { character } '{{' 'endraw' '}}'   (* contents are template text *)
+escaped = '\{{'   (* template text `{{` *)
+        | '\\{{'   (* template text `\` followed by a tag *)
+(* any '{{' can be written '{{-' and any '}}' '-}}' to remove whitespace before or after the tag *)
+comment = '{#' { character } '#}'   (* skipped by the tokenizer, allowed anywhere outside '{{ }}' *)
 
-for = '{{' 'for' identifier 'in' call '}}' statement '{{' 'end' '}}'
-if = '{{' if expression '}}' { statement } [ '{{' else '}}'  { statement }] '{{' end '}}'
+for = '{{' 'for' identifier [ ',' identifier ] 'in' expression [ 'if' expression ] '}}' { statement }
+        [ '{{' 'else' '}}' { statement } ] '{{' 'end' '}}'
+if = '{{' if expression '}}' { statement }
+        { '{{' ( 'else' 'if' | 'elif' ) expression '}}' { statement } }
+        [ '{{' else '}}'  { statement }] '{{' end '}}'
+set = '{{' ( 'set' | 'let' ) identifier ( ( '=' expression '}}' ) | ( '}}' { statement } '{{' 'end' '}}' ) )
+with = '{{' 'with' expression [ 'as' identifier ] '}}' { statement } '{{' 'end' '}}'
+when = '{{' 'when' expression '}}' { '{{' 'is' expression { ',' expression } '}}' { statement } }
+        [ '{{' 'else' '}}' { statement } ] '{{' 'end' '}}'
 */
 
-/// Renders `source` with given `context`
+/// Options changing how templates are rendered
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Options {
+    /// if true comparing values of different types with `<`, `<=`, `>` or `>=` is an error,
+    /// else the comparison is false
+    pub strict_comparisons: bool,
+    /// if true `for` iterates objects in order of their keys, else in insertion order
+    pub sorted_objects: bool,
+    /// if true `for` treats null as empty, rendering its `else` block, else iterating null is an error
+    pub null_is_empty: bool,
+    /// largest number of integers a range can have, longer ranges are an error
+    pub max_range_length: usize,
+    /// if true the first newline after a block tag like `{{ for x in y }}` or `{{ end }}` is removed
+    pub trim_blocks: bool,
+    /// if true spaces and tabs from start of line up to a block tag are removed
+    pub lstrip_blocks: bool
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            strict_comparisons: true,
+            sorted_objects: false,
+            null_is_empty: true,
+            max_range_length: 10_000,
+            trim_blocks: false,
+            lstrip_blocks: false
+        }
+    }
+}
+
+/// Renders `source` with given `context` and default `Options`, returns `RenderError` if `source`
+/// cannot be tokenized, parsed or interpreted, or if `context_json` is not valid JSON, only
+/// built-in filters, functions and tests are available, use `render_with_interperter` for
+/// registered ones
+/// 
+/// # Arguments
+/// 
+/// * `source` - string to be rendered
+/// * `context_json` - the context to be used for rendering
+pub fn render(source: &str, context_json: &str) -> Result<String, RenderError> {
+    render_with_options(source, context_json, &Options::default())
+}
+
+/// Renders `source` with given `context` and `options`, returns `RenderError` if `source` cannot
+/// be tokenized, parsed or interpreted, or if `context_json` is not valid JSON, only built-in
+/// filters, functions and tests are available, use `render_with_interperter` for registered ones
 /// 
 /// # Arguments
 /// 
 /// * `source` - string to be rendered
 /// * `context_json` - the context to be used for rendering
-#[wasm_bindgen]
-pub fn render(source: &str, context_json: &str) -> String {
-    let binding = Tokenizer::new(source.as_bytes());
-    let tokens = binding.tokenize();
+/// * `options` - options changing how `source` is rendered
+pub fn render_with_options(source: &str, context_json: &str, options: &Options) -> Result<String, RenderError> {
+    let binding = Tokenizer::new(source.as_bytes()).with_options(*options);
+    let tokens = binding.tokenize()?;
     let binding = Parser::new(&tokens);
-    let statements = binding.parse();
-    let value: Value = serde_json::from_str(context_json).unwrap();
-    let interperter = Interperter::new(value);
+    let (statements, diagnostics) = binding.parse();
+    if !diagnostics.is_empty() {
+        return Err(RenderError::Parse(diagnostics));
+    }
+    let value: Value = serde_json::from_str(context_json)
+        .map_err(|error| RenderError::Context { message: error.to_string() })?;
+    let interperter = Interperter::new(value).with_options(*options);
     interperter.interpret(&statements)
 }
 
+/// Renders `source` with given `interperter`, which holds the context, options and registered
+/// filters, functions and tests, returns `RenderError` if `source` cannot be tokenized, parsed or
+/// interpreted, use `Interperter::set_context` to reuse the interperter with another context
+/// 
+/// # Arguments
+/// 
+/// * `source` - string to be rendered
+/// * `interperter` - the interperter used for rendering
+pub fn render_with_interperter(source: &str, interperter: &Interperter) -> Result<String, RenderError> {
+    let binding = Tokenizer::new(source.as_bytes()).with_options(interperter.options());
+    let tokens = binding.tokenize()?;
+    let binding = Parser::new(&tokens);
+    let (statements, diagnostics) = binding.parse();
+    if !diagnostics.is_empty() {
+        return Err(RenderError::Parse(diagnostics));
+    }
+    interperter.interpret(&statements)
+}
+
+/// Renders `source` with given `context` for JavaScript, errors are thrown as formatted diagnostics
+/// 
+/// # Arguments
+/// 
+/// * `source` - string to be rendered
+/// * `context_json` - the context to be used for rendering
+#[wasm_bindgen(js_name = render)]
+pub fn render_js(source: &str, context_json: &str) -> Result<String, JsValue> {
+    render(source, context_json)
+        .map_err(|error| JsValue::from_str(&error.format(source.as_bytes(), &FormatOptions::default())))
+}
+
 /// Renders contents of `source_path` file with given context in `context_json_path` in JSON
-/// format, files at path must be valid UTF-8
+/// format, files at path must be valid UTF-8, returns `RenderError` if rendering fails or
+/// `RenderError::Io` if a file cannot be read or written
+/// 
+/// # Arguments
+/// `source_path` - path to file to be rendered
+/// `context_json_path` - path to JSON file with context to be used for rendering
+pub fn render_file(source_path: &str, context_json_path: &str) -> Result<(), RenderError> {
+    render_file_with(source_path, context_json_path, |_| {})
+}
+
+/// Renders contents of `source_path` file like `render_file`, `register` is called with the
+/// interperter before rendering to register custom filters, functions and tests
 /// 
 /// # Arguments
 /// `source_path` - path to file to be rendered
 /// `context_json_path` - path to JSON file with context to be used for rendering
-pub fn render_file(source_path: &str, context_json_path: &str) {
-    let source = fs::read_to_string(&source_path)
-        .expect("Should have been able to read the file");
-    let json = fs::read_to_string(&context_json_path)
-        .expect("Should have been able to read the file");
-    let output = render(&source, &json);
+/// `register` - registers custom filters, functions and tests on the interperter
+pub fn render_file_with(source_path: &str, context_json_path: &str, register: impl FnOnce(&mut Interperter)) -> Result<(), RenderError> {
+    let source = fs::read_to_string(source_path)
+        .map_err(|error| io_error(source_path, error))?;
+    let json = fs::read_to_string(context_json_path)
+        .map_err(|error| io_error(context_json_path, error))?;
+    let value: Value = serde_json::from_str(&json)
+        .map_err(|error| RenderError::Context { message: error.to_string() })?;
+    let mut interperter = Interperter::new(value);
+    register(&mut interperter);
+    let output = render_with_interperter(&source, &interperter)?;
     println!("{}", &output);
     let path = Path::new(&source_path);
-    let file_stem = path.file_stem().expect("Unable to parse source filename");
-    let extension = path.extension().expect("Unable to parse source file extension");
-    let mut file = fs::File::create([file_stem.to_str().unwrap(), "_yartle_out.", extension.to_str().unwrap()].join(""))
-        .expect("Error writing file");
-    file.write_all(output.as_bytes()).unwrap();
+    let file_stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("output");
+    let output_path = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => [file_stem, "_yartle_out.", extension].join(""),
+        None => [file_stem, "_yartle_out"].join("")
+    };
+    fs::File::create(&output_path)
+        .and_then(|mut file| file.write_all(output.as_bytes()))
+        .map_err(|error| io_error(&output_path, error))
+}
+
+/// Returns `RenderError::Io` for `error` encountered reading or writing file at `path`
+/// 
+/// # Arguments
+/// 
+/// * `path` - path of the file
+/// * `error` - the error encountered
+fn io_error(path: &str, error: std::io::Error) -> RenderError {
+    RenderError::Io { path: path.to_string(), message: error.to_string() }
 }
 
 /// Represents tokens
@@ -80,22 +218,161 @@ enum TokenType {
     In,
     If,
     Else,
+    Elif,
     When,
+    Is,
+    Break,
+    Continue,
+    Set,
+    Let,
+    With,
+    As,
+    Comma,
     Identifier,
     String,
+    Number,
+    True,
+    False,
+    Null,
     TempalteLiteral,
     Dot,
+    DoubleDot,
+    DoubleDotEqual,
     End,
     DoubleEquals,
     ExclaimationEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
     Exclaimation,
+    Minus,
+    Plus,
+    Star,
+    Slash,
+    Percent,
+    LeftParenthesis,
+    RightParenthesis,
+    LeftBracket,
+    RightBracket,
     DoubleAmpersand,
-    DoublePipe
+    DoublePipe,
+    Pipe,
+    Equal,
+    Comment
+}
+
+impl TokenType {
+    /// Returns how the token type is referred to in diagnostics
+    fn description(&self) -> &'static str {
+        match self {
+            TokenType::DoubleLeftBrackets => "`{{`",
+            TokenType::DoubleRightBrackets => "`}}`",
+            TokenType::For => "`for`",
+            TokenType::In => "`in`",
+            TokenType::If => "`if`",
+            TokenType::Else => "`else`",
+            TokenType::Elif => "`elif`",
+            TokenType::When => "`when`",
+            TokenType::Break => "`break`",
+            TokenType::Continue => "`continue`",
+            TokenType::Set => "`set`",
+            TokenType::Let => "`let`",
+            TokenType::With => "`with`",
+            TokenType::As => "`as`",
+            TokenType::Is => "`is`",
+            TokenType::Comma => "`,`",
+            TokenType::Identifier => "identifier",
+            TokenType::String => "string literal",
+            TokenType::Number => "number literal",
+            TokenType::True => "`true`",
+            TokenType::False => "`false`",
+            TokenType::Null => "`null`",
+            TokenType::TempalteLiteral => "template text",
+            TokenType::Dot => "`.`",
+            TokenType::DoubleDot => "`..`",
+            TokenType::DoubleDotEqual => "`..=`",
+            TokenType::End => "`end`",
+            TokenType::DoubleEquals => "`==`",
+            TokenType::ExclaimationEqual => "`!=`",
+            TokenType::Less => "`<`",
+            TokenType::LessEqual => "`<=`",
+            TokenType::Greater => "`>`",
+            TokenType::GreaterEqual => "`>=`",
+            TokenType::Exclaimation => "`!`",
+            TokenType::Minus => "`-`",
+            TokenType::Plus => "`+`",
+            TokenType::Star => "`*`",
+            TokenType::Slash => "`/`",
+            TokenType::Percent => "`%`",
+            TokenType::LeftParenthesis => "`(`",
+            TokenType::RightParenthesis => "`)`",
+            TokenType::LeftBracket => "`[`",
+            TokenType::RightBracket => "`]`",
+            TokenType::DoubleAmpersand => "`&&`",
+            TokenType::DoublePipe => "`||`",
+            TokenType::Pipe => "`|`",
+            TokenType::Equal => "`=`",
+            TokenType::Comment => "comment"
+        }
+    }
+}
+
+/// Represents a range of bytes in source, with line and column of the first byte
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub struct Span {
+    /// byte offset of first byte
+    pub start: usize,
+    /// byte offset after last byte
+    pub end: usize,
+    /// line number of first byte, starting at 1
+    pub line: usize,
+    /// column number of first byte in characters, starting at 1
+    pub column: usize
+}
+
+impl Span {
+    /// Returns a span starting at start of `self` and ending at end of `other`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `other` - the span to extend to
+    pub fn to(&self, other: Span) -> Span {
+        Span { end: other.end, ..*self }
+    }
 }
 
 /// Represents a token
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Token<'a> {
     token_type: TokenType,
-    token_value: &'a [u8]
+    token_value: &'a [u8],
+    span: Span
+}
+
+impl<'a> Token<'a> {
+    /// Returns the location of the token in source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the text of the token in source
+    pub fn value(&self) -> &'a [u8] {
+        self.token_value
+    }
+
+    /// Returns true if the token is a comment, only returned by tokenizers created with
+    /// `Tokenizer::with_comments`
+    /// 
+    /// ```
+    /// use yartl_engine::tokenizer::Tokenizer;
+    /// 
+    /// let tokens = Tokenizer::new(b"a{# note #}b").with_comments(true).tokenize().unwrap();
+    /// let comments: Vec<&[u8]> = tokens.iter().filter(|token| token.is_comment()).map(|token| token.value()).collect();
+    /// assert_eq!(comments, vec![b"{# note #}".as_slice()]);
+    /// assert_eq!(tokens[1].span().start, 1);
+    /// ```
+    pub fn is_comment(&self) -> bool {
+        self.token_type == TokenType::Comment
+    }
 }
@@ -1,11 +1,17 @@
-use std::env;
+use std::{env, fs, io::{self, IsTerminal}, process};
 
-use yartl_engine::render_file;
+use yartl_engine::{render_file, message_formatter::FormatOptions};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() != 3 {
-        panic!("Usage: yartl_engine path_to_source path_to_json_context")
+        eprintln!("Usage: yartl_engine path_to_source path_to_json_context");
+        process::exit(2);
+    }
+    if let Err(error) = render_file(&args[1], &args[2]) {
+        let source = fs::read(&args[1]).unwrap_or_default();
+        let options = FormatOptions { color: io::stderr().is_terminal(), ..FormatOptions::default() };
+        eprint!("{}", error.format(&source, &options));
+        process::exit(1);
     }
-    render_file(&args[1], &args[2]);
 }
@@ -1,47 +1,236 @@
-// Used for formatting errors, experimental implementation, to be changed.
+// Used for formatting diagnostics with the source they refer to
 
-// struct ByteLocation<'a> {
-//     line_number: usize,
-//     column_number: usize,
-//     line: &'a str
-// }
+use crate::Span;
 
-pub fn format<'a>(source: &'a [u8], index: usize, message: &'a str) -> String {
-    format!("{}\n{}", message, get_location(source, index))
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Represents a message attached to a location in source
+#[derive(PartialEq, Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String
+}
+
+/// Represents a problem found in source, with an error code, a primary label pointing at the
+/// problem, optional secondary labels pointing at related locations and help notes
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub label: Label,
+    pub secondary_labels: Vec<Label>,
+    pub help: Vec<String>
 }
 
-fn get_location(source: &[u8], index: usize) -> String {
-    if index > source.len() {
-        panic!("index out of bounds");
+impl Diagnostic {
+    /// Returns a diagnostic with an unlabelled primary span
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - the error code, e.g. `E0001`
+    /// * `message` - description of the problem
+    /// * `span` - location of the problem
+    pub fn new(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            label: Label { span, message: String::new() },
+            secondary_labels: Vec::new(),
+            help: Vec::new()
+        }
+    }
+
+    /// Sets message of the primary label
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - the text displayed under the primary span
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        self.label.message = message.into();
+        self
+    }
+
+    /// Adds a secondary label, e.g. where an unclosed block was opened
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - the related location
+    /// * `message` - the text displayed under `span`
+    pub fn with_secondary_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary_labels.push(Label { span, message: message.into() });
+        self
+    }
+
+    /// Adds a help note displayed after the source
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - the note
+    pub fn with_help(mut self, message: impl Into<String>) -> Self {
+        self.help.push(message.into());
+        self
     }
-    let mut i: usize = 0;
-    let mut line_number: usize = 1;
-    let mut line_start: usize = 0;
-    while i != index  {
-        if source[i] == b'\n' {
-            line_number += 1;
-            line_start = i;
+}
+
+/// Options used when formatting diagnostics
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct FormatOptions {
+    /// wrap output in ANSI colour codes
+    pub color: bool,
+    /// number of lines displayed before and after each labelled line
+    pub context_lines: usize
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { color: false, context_lines: 1 }
+    }
+}
+
+/// Returns `diagnostic` formatted with the lines of `source` it refers to, labels outside of
+/// `source` are clamped to its end
+///
+/// # Arguments
+///
+/// * `source` - the source `diagnostic` refers to
+/// * `diagnostic` - the diagnostic to format
+/// * `options` - formatting options
+pub fn format(source: &[u8], diagnostic: &Diagnostic, options: &FormatOptions) -> String {
+    let paint = |color: &str, text: &str| {
+        if options.color { format!("{}{}{}", color, text, RESET) } else { text.to_string() }
+    };
+    let lines = get_lines(source);
+    let primary = locate(source, &lines, &diagnostic.label, true);
+    let mut labels = vec![primary];
+    for label in &diagnostic.secondary_labels {
+        labels.push(locate(source, &lines, label, false));
+    }
+    labels.sort_by_key(|label| (label.line, label.start_column));
+
+    let mut displayed: Vec<usize> = Vec::new();
+    for label in &labels {
+        let first = label.line.saturating_sub(options.context_lines);
+        let last = (label.line + options.context_lines).min(lines.len() - 1);
+        displayed.extend(first ..= last);
+    }
+    displayed.sort_unstable();
+    displayed.dedup();
+    let gutter_width = (displayed.last().unwrap_or(&0) + 1).to_string().len();
+    let gutter = |text: &str| paint(BLUE, &format!("{:>1$} |", text, gutter_width));
+
+    let mut output = format!(
+        "{}{}\n{}{} {}:{}\n{}\n",
+        paint(RED, &format!("error[{}]", diagnostic.code)),
+        paint(BOLD, &format!(": {}", diagnostic.message)),
+        " ".repeat(gutter_width),
+        paint(BLUE, "-->"),
+        primary.line + 1,
+        primary.start_column + 1,
+        gutter("")
+    );
+    let mut previous: Option<usize> = None;
+    for line in displayed {
+        if previous.is_some_and(|previous| previous + 1 != line) {
+            output.push_str(&paint(BLUE, "...\n"));
         }
-        i += 1;
-    }
-    let column = index - line_start + 1;
-    let line_end = index_of(source, b'\n', index).unwrap_or(source.len());
-    format!(
-        "Line number: {}, Column number: {}\nLine: {}\n{:>4$}",
-        line_number,
-        column,
-        std::str::from_utf8(&source[line_start .. line_end]).unwrap(),
-        '^',
-        column + 6 // compensate for "Line: " prefix on previous line
-    )
-}
-
-fn index_of(source: &[u8], byte: u8, mut offset: usize) -> Result<usize, &'static str> {
-    while offset != source.len() {
-        if source[offset] == byte {
-            return Ok(offset);
+        previous = Some(line);
+        let text = String::from_utf8_lossy(&source[lines[line].0 .. lines[line].1]);
+        output.push_str(&format!("{} {}\n", gutter(&(line + 1).to_string()), text.trim_end_matches('\r')));
+        for label in labels.iter().filter(|label| label.line == line) {
+            // keep tabs so underline lines up with source
+            let padding: String = text.chars()
+                .take(label.start_column)
+                .map(|character| if character == '\t' { '\t' } else { ' ' })
+                .collect();
+            let (marker, color) = if label.primary { ('^', RED) } else { ('-', BLUE) };
+            let underline = marker.to_string().repeat((label.end_column - label.start_column).max(1));
+            let annotation = if label.message.is_empty() { underline } else { format!("{} {}", underline, label.message) };
+            output.push_str(&format!("{} {}{}\n", gutter(""), padding, paint(color, &annotation)));
         }
-        offset += 1;
     }
-    Err("Not found")
-}
\ No newline at end of file
+    output.push_str(&format!("{}\n", gutter("")));
+    for help in &diagnostic.help {
+        output.push_str(&format!("{} {} {}\n", " ".repeat(gutter_width), paint(BOLD, "= help:"), help));
+    }
+    output
+}
+
+/// Returns start and end (excluding newline) byte offsets of every line in `source`
+fn get_lines(source: &[u8]) -> Vec<(usize, usize)> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, byte) in source.iter().enumerate() {
+        if *byte == b'\n' {
+            lines.push((start, i));
+            start = i + 1;
+        }
+    }
+    lines.push((start, source.len()));
+    lines
+}
+
+/// Represents a label resolved to the line it starts on, columns are counted in characters from 0
+#[derive(Copy, Clone)]
+struct LocatedLabel<'a> {
+    line: usize,
+    start_column: usize,
+    end_column: usize,
+    message: &'a str,
+    primary: bool
+}
+
+/// Returns `label` resolved against `lines`, end column is clamped to end of the line `label` starts on
+fn locate<'a>(source: &[u8], lines: &[(usize, usize)], label: &'a Label, primary: bool) -> LocatedLabel<'a> {
+    let start = label.span.start.min(source.len());
+    let end = label.span.end.clamp(start, source.len());
+    let line = lines.partition_point(|line| line.1 < start).min(lines.len() - 1);
+    let count = |from: usize, to: usize| source[from .. to].iter().filter(|byte| (**byte & 0xC0) != 0x80).count();
+    let start_column = count(lines[line].0, start);
+    let end_column = start_column + count(start, end.min(lines[line].1));
+    LocatedLabel { line, start_column, end_column, message: &label.message, primary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let source = "<ul>\n{{ for item in items }}\n  <li>{{ item # }}</li>\n</ul>\n".as_bytes();
+        let diagnostic = Diagnostic::new("E0001", "Invalid character", Span { start: 43, end: 44, line: 3, column: 15 })
+            .with_label("not valid here")
+            .with_secondary_label(Span { start: 5, end: 28, line: 2, column: 1 }, "inside this `for`")
+            .with_help("remove the character");
+        assert_eq!(format(source, &diagnostic, &FormatOptions::default()), [
+            "error[E0001]: Invalid character",
+            " --> 3:15",
+            "  |",
+            "1 | <ul>",
+            "2 | {{ for item in items }}",
+            "  | ----------------------- inside this `for`",
+            "3 |   <li>{{ item # }}</li>",
+            "  |               ^ not valid here",
+            "4 | </ul>",
+            "  |",
+            "  = help: remove the character",
+            ""
+        ].join("\n"));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let diagnostic = Diagnostic::new("E0101", "Unexpected end of input", Span { start: 10, end: 12, line: 1, column: 11 });
+        assert_eq!(format("é".as_bytes(), &diagnostic, &FormatOptions::default()), [
+            "error[E0101]: Unexpected end of input",
+            " --> 1:2",
+            "  |",
+            "1 | é",
+            "  |  ^",
+            "  |",
+            ""
+        ].join("\n"));
+    }
+}
@@ -1,55 +1,168 @@
-use std::{cell::RefCell};
+use std::cell::RefCell;
 
-use crate::{Token, TokenType, statement::{Statement, self}, expression::{Expression, self, UnaryExpression, BinaryExpression}};
+use crate::{Token, TokenType, Span, message_formatter::Diagnostic, statement::{Statement, self, WhenArm, IfBranch}, expression::{Expression, self, UnaryExpression, BinaryExpression, FilterExpression, TestExpression, FunctionCallExpression, IndexExpression, LiteralExpression, ArgumentList}};
+
+/// Result of parsing a grammar rule, boxed to keep `Ok` path small
+type ParseResult<T> = Result<T, Box<Diagnostic>>;
+
+/// Key or index identifier, instance identifier, iterated expression and condition of a for statement tag
+type ForHeader<'a> = (Option<&'a [u8]>, &'a [u8], Expression<'a>, Option<Expression<'a>>);
 
 /// Creates AST with given tokens
 pub struct Parser<'a> {
     // TODO: do not hold refrence, take as argument?
     tokens: &'a Vec<Token<'a>>,
-    i: RefCell<usize>
+    i: RefCell<usize>,
+    /// syntax errors recovered from so far
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    /// number of for loop bodies being parsed, `break` and `continue` are only allowed inside one
+    loop_depth: RefCell<usize>
 }
 
-impl<'a, 'b> Parser<'a> {
+impl<'a> Parser<'a> {
     /// Returns parser, to be used to create AST from `tokens`
     /// 
     /// # Arguments
     /// `tokens` - tokens to be parsed
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Parser { tokens, i: RefCell::new(0) }
+    pub fn new(tokens: &'a Vec<Token<'a>>) -> Self {
+        Parser { tokens, i: RefCell::new(0), diagnostics: RefCell::new(Vec::new()), loop_depth: RefCell::new(0) }
     }
 
     /// Increments parser to next token and returns it
-    fn next_token(&self) -> Option<&Token> {
+    fn next_token(&self) -> Option<&'a Token<'a>> {
         let old = *self.i.borrow();
         self.i.replace(old + 1);
-        // println!("Next token: {:?}", self.current_token());
         self.current_token()
     }
 
-    fn current_token(&self) -> Option<&Token> {
+    fn current_token(&self) -> Option<&'a Token<'a>> {
         let i = self.i.borrow();
-        if self.tokens.len() <= *i { None } else { Some(&self.tokens[*i]) }
+        self.tokens.get(*i)
+    }
+
+    /// Returns token after current token without incrementing parser
+    fn peek_token(&self) -> Option<&'a Token<'a>> {
+        self.tokens.get(*self.i.borrow() + 1)
+    }
+
+    /// Returns span of current token, or an empty span after last token if at end of input
+    fn current_span(&self) -> Span {
+        if let Some(token) = self.current_token() {
+            return token.span;
+        }
+        match self.tokens.last() {
+            Some(token) => {
+                let mut span = Span { start: token.span.end, ..token.span };
+                for byte in token.token_value {
+                    if *byte == b'\n' {
+                        span.line += 1;
+                        span.column = 1;
+                    } else if (byte & 0xC0) != 0x80 {
+                        span.column += 1;
+                    }
+                }
+                span
+            }
+            None => Span { start: 0, end: 0, line: 1, column: 1 }
+        }
+    }
+
+    /// Returns span of last consumed token
+    fn previous_span(&self) -> Span {
+        let i = *self.i.borrow();
+        self.tokens[i - 1].span
     }
 
-    /// Checks if currently on given `TokenType`, increments parser if yes, panics if not
+    /// Returns diagnostic for current token not being `expected`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `expected` - description of what was expected instead of current token
+    fn unexpected(&self, expected: &str) -> Box<Diagnostic> {
+        let diagnostic = match self.current_token() {
+            Some(token) => Diagnostic::new("E0102", format!("Unexpected {}", token.token_type.description()), token.span),
+            None => Diagnostic::new("E0101", "Unexpected end of input", self.current_span())
+        };
+        Box::new(diagnostic.with_label(format!("expected {}", expected)))
+    }
+
+    /// Checks if currently on given `TokenType`, increments parser if yes, errors if not
     /// 
     /// # Arguments
     /// 
     /// * `token_type` - expected current `TokenType`
-    fn expect(&self, token_type: TokenType) {
-        self.on(token_type);
+    fn expect(&self, token_type: TokenType) -> ParseResult<&'a Token<'a>> {
+        let token = self.on(token_type)?;
         self.next_token();
+        Ok(token)
     }
 
-    /// Checks if currently on given `TokenType`, panics if not
+    /// Checks if currently on given `TokenType`, errors if not
     /// 
     /// # Arguments
     /// 
     /// * `token_type` - expected current `TokenType`
-    fn on(&self, token_type: TokenType) {
-        let token = self.current_token().expect(&format!("Unexpected end of input, expected token: {:?}", token_type));
-        if token.token_type != token_type {
-            panic!("Unexpected token: {:?}, expected: {:?}", token.token_type, token_type);
+    fn on(&self, token_type: TokenType) -> ParseResult<&'a Token<'a>> {
+        match self.current_token() {
+            Some(token) if token.token_type == token_type => Ok(token),
+            _ => Err(self.unexpected(token_type.description()))
+        }
+    }
+
+    /// Checks if currently on `end` of block opened by `opening`, increments parser if yes,
+    /// errors pointing at `opening` if not
+    /// 
+    /// # Arguments
+    /// 
+    /// * `opening` - span of the tag that opened the block
+    /// * `keyword` - keyword of the block, e.g. `for`
+    fn expect_end(&self, opening: Span, keyword: &str) -> ParseResult<()> {
+        if self.is_on(TokenType::End) {
+            self.next_token();
+            return Ok(());
+        }
+        let found = match self.current_token() {
+            Some(token) => token.token_type.description(),
+            None => "end of input"
+        };
+        Err(Box::new(
+            Diagnostic::new("E0103", format!("Unclosed `{}` block", keyword), self.current_span())
+                .with_label(format!("expected `end`, found {}", found))
+                .with_secondary_label(opening, format!("`{}` opened here", keyword))
+                .with_help("close the block with `{{ end }}`")
+        ))
+    }
+
+    /// Returns `Option::Some` with value of `result` if it is `Ok`, else records the diagnostic and
+    /// skips to the end of the current tag
+    /// 
+    /// # Arguments
+    /// 
+    /// * `result` - the result to recover from
+    fn recover<T>(&self, result: ParseResult<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(diagnostic) => {
+                self.diagnostics.borrow_mut().push(*diagnostic);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until after the `}}` of the current tag, or until the `{{` of the next tag
+    fn synchronize(&self) {
+        while let Some(token) = self.current_token() {
+            match token.token_type {
+                TokenType::DoubleRightBrackets => {
+                    self.next_token();
+                    return;
+                }
+                TokenType::DoubleLeftBrackets => return,
+                _ => {
+                    self.next_token();
+                }
+            }
         }
     }
 
@@ -59,203 +172,722 @@ impl<'a, 'b> Parser<'a> {
     /// 
     /// * `token_type` - the `TokenType` to compare to
     fn is_on(&self, token_type: TokenType) -> bool {
-        let token = self.current_token().expect(&format!("Unexpected end of input, expected more tokens"));
-        if token.token_type != token_type {
-            return false;
+        match self.current_token() {
+            Some(token) => token.token_type == token_type,
+            None => false
         }
-        true
     }
 
     /// Parses tokens refrenceced by instance, returns `Vec::<Statement>` that represent a series of ASTs
-    pub fn parse(&self) -> Vec::<Statement> {
+    /// and a diagnostic for every syntax error, statements with errors are left out of the ASTs
+    pub fn parse(&self) -> (Vec::<Statement<'a>>, Vec<Diagnostic>) {
+        let mut statements = self.parse_block();
+        while let Some(token) = self.current_token() {
+            // only `end`, `else`, `elif` and `is` stop a block early
+            self.diagnostics.borrow_mut().push(
+                Diagnostic::new("E0104", format!("Unexpected {}", token.token_type.description()), token.span)
+                    .with_label("no open block to close")
+                    .with_help("remove this tag or open a block with `{{ for }}`, `{{ if }}` or `{{ when }}` before it")
+            );
+            self.synchronize();
+            statements.extend(self.parse_block());
+        }
+        (statements, self.diagnostics.take())
+    }
+
+    /// Parses statements until end of input or until an `end`, `else`, `elif` or `is` tag is reached,
+    /// the `{{` of the tag is consumed
+    fn parse_block(&self) -> Vec::<Statement<'a>> {
         let mut statements: Vec::<Statement> = Vec::new();
         loop {
-            if self.current_token() == None {
+            let token = match self.current_token() {
+                Some(token) => token,
+                None => return statements
+            };
+            // can this be more elegant? does not fit in grammar rules
+            if token.token_type == TokenType::DoubleLeftBrackets
+                && self.peek_token().is_some_and(|token| matches!(token.token_type, TokenType::End | TokenType::Else | TokenType::Elif | TokenType::Is)) {
+                self.next_token();
                 return statements;
             }
-            match self.parse_statement() {
-                Some(statement) => {
-                    statements.push(statement);
-                }
-                None => {
-                    return statements
-                }
+            let start = *self.i.borrow();
+            if let Some(Some(statement)) = self.recover(self.parse_statement()) {
+                statements.push(statement);
+            }
+            // always make progress, even if recovery could not
+            if *self.i.borrow() == start {
+                self.next_token();
             }
         }
     }
 
-    /// Parse starting at current token, convert to AST, Option::None if at end of lexical statement  
-    fn parse_statement(&self) -> Option<Statement> {
-        match self.current_token()?.token_type {
+    /// Parse starting at current token, convert to AST, Option::None if statement was left out
+    /// because of a recovered syntax error
+    fn parse_statement(&self) -> ParseResult<Option<Statement<'a>>> {
+        let token = match self.current_token() {
+            Some(token) => token,
+            None => return Err(self.unexpected("statement"))
+        };
+        match token.token_type {
             TokenType::DoubleLeftBrackets => {
-                match self.next_token()?.token_type {
-                    TokenType::For => Some(self.parse_for()),
-                    TokenType::If => Some(self.parse_if()),
-                    // can this be more elegant? does not fit in grammar rules
-                    TokenType::End => None,
-                    TokenType::Else => None,
+                let token = match self.next_token() {
+                    Some(token) => token,
+                    None => return Err(self.unexpected("expression"))
+                };
+                match token.token_type {
+                    TokenType::For => Ok(self.parse_for()),
+                    TokenType::If => Ok(self.parse_if()),
+                    TokenType::When => Ok(self.parse_when()),
+                    TokenType::Set | TokenType::Let => Ok(self.parse_set()),
+                    TokenType::With => Ok(self.parse_with()),
+                    TokenType::Break | TokenType::Continue => {
+                        let start = self.previous_span();
+                        self.next_token();
+                        if *self.loop_depth.borrow() == 0 {
+                            return Err(Box::new(
+                                Diagnostic::new("E0107", format!("{} outside of loop", token.token_type.description()), token.span)
+                                    .with_label("only allowed inside `for`")
+                            ));
+                        }
+                        self.expect(TokenType::DoubleRightBrackets)?;
+                        let span = start.to(self.previous_span());
+                        Ok(Some(match token.token_type {
+                            TokenType::Break => Statement::Break(statement::BreakStatement { span }),
+                            _ => Statement::Continue(statement::ContinueStatement { span })
+                        }))
+                    }
                     _ => {
-                        let statement = Some(Statement::Expression(self.parse_expression()));
-                        self.expect(TokenType::DoubleRightBrackets);
-                        statement
+                        let statement = Statement::Expression(self.parse_expression()?);
+                        self.expect(TokenType::DoubleRightBrackets)?;
+                        Ok(Some(statement))
                     }
                 }
             }
             TokenType::TempalteLiteral => {
                 let expression = Expression::TemplateLiteral(expression::TemplateLiteralExpression {
-                    value: self.current_token()?.token_value
+                    value: token.token_value,
+                    span: token.span
                 });
                 self.next_token();
-                Some(Statement::Expression(expression))
-            }
-            _ => {
-                todo!("{:?}", self.current_token()?);
+                Ok(Some(Statement::Expression(expression)))
             }
+            _ => Err(self.unexpected("`{{` or template text"))
         }
     }
 
     /// Parse expression starting at current token
-    fn parse_expression(&self) -> Expression {
+    fn parse_expression(&self) -> ParseResult<Expression<'a>> {
         self.parse_or()
     }
 
     /// Parse or expression starting at current token
-    fn parse_or(&self) -> Expression {
-        let mut left = self.parse_and();
+    fn parse_or(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_and()?;
         while self.is_on(TokenType::DoublePipe) {
             let operator = self.current_token().unwrap();
             self.next_token();
-            let right = Box::new(Statement::Expression(self.parse_and()));
+            let right = Box::new(Statement::Expression(self.parse_and()?));
             left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
                 left: Box::new(Statement::Expression(left)),
                 operator,
                 right
             });
         }
-        left
+        Ok(left)
     }
 
     /// Parse and expression starting at current token
-    fn parse_and(&self) -> Expression {
-        let mut left = self.parse_equality();
+    fn parse_and(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_equality()?;
         while self.is_on(TokenType::DoubleAmpersand) {
             let operator = self.current_token().unwrap();
             self.next_token();
-            let right = Box::new(Statement::Expression(self.parse_equality()));
+            let right = Box::new(Statement::Expression(self.parse_equality()?));
             left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
                 left: Box::new(Statement::Expression(left)),
                 operator,
                 right
             });
         }
-        left
+        Ok(left)
     }
 
     /// Parse equality expression starting at current token
-    fn parse_equality(&self) -> Expression {
-        let mut left = self.parse_unary();
+    fn parse_equality(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_comparison()?;
         while self.is_on(TokenType::DoubleEquals) || self.is_on(TokenType::ExclaimationEqual) {
             let operator = self.current_token().unwrap();
             self.next_token();
-            let right = Box::new(Statement::Expression(self.parse_unary()));
+            let right = Box::new(Statement::Expression(self.parse_comparison()?));
+            left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
+                left: Box::new(Statement::Expression(left)),
+                operator,
+                right
+            });
+        }
+        Ok(left)
+    }
+
+    /// Parse comparison expression starting at current token
+    fn parse_comparison(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_test()?;
+        while self.is_on(TokenType::Less) || self.is_on(TokenType::LessEqual)
+            || self.is_on(TokenType::Greater) || self.is_on(TokenType::GreaterEqual) {
+            let operator = self.current_token().unwrap();
+            self.next_token();
+            let right = Box::new(Statement::Expression(self.parse_test()?));
             left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
                 left: Box::new(Statement::Expression(left)),
                 operator,
                 right
             });
         }
-        left
+        Ok(left)
+    }
+
+    /// Parse test expression starting at current token, `not` negates the test only when followed
+    /// by the test name so it can still be used as a test name
+    fn parse_test(&self) -> ParseResult<Expression<'a>> {
+        let value = self.parse_range()?;
+        if !self.is_on(TokenType::Is) {
+            return Ok(value);
+        }
+        self.next_token();
+        let negated = self.current_token().is_some_and(|token| token.token_value == b"not")
+            && self.peek_token().is_some_and(|token| token.token_type == TokenType::Identifier);
+        if negated {
+            self.next_token();
+        }
+        let name = self.expect(TokenType::Identifier)?;
+        let arguments = if self.is_on(TokenType::LeftParenthesis) {
+            self.parse_arguments()?
+        } else {
+            ArgumentList::default()
+        };
+        Ok(Expression::Test(TestExpression {
+            span: value.span().to(self.previous_span()),
+            value: Box::new(Statement::Expression(value)),
+            name,
+            negated,
+            arguments
+        }))
+    }
+
+    /// Parse range expression starting at current token, ranges cannot be chained
+    fn parse_range(&self) -> ParseResult<Expression<'a>> {
+        let left = self.parse_term()?;
+        if !self.is_on(TokenType::DoubleDot) && !self.is_on(TokenType::DoubleDotEqual) {
+            return Ok(left);
+        }
+        let operator = self.current_token().unwrap();
+        self.next_token();
+        let right = Box::new(Statement::Expression(self.parse_term()?));
+        Ok(Expression::Binary(BinaryExpression {
+            span: left.span().to(right.span()),
+            left: Box::new(Statement::Expression(left)),
+            operator,
+            right
+        }))
+    }
+
+    /// Parse term (addition and subtraction) expression starting at current token
+    fn parse_term(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_factor()?;
+        while self.is_on(TokenType::Plus) || self.is_on(TokenType::Minus) {
+            let operator = self.current_token().unwrap();
+            self.next_token();
+            let right = Box::new(Statement::Expression(self.parse_factor()?));
+            left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
+                left: Box::new(Statement::Expression(left)),
+                operator,
+                right
+            });
+        }
+        Ok(left)
+    }
+
+    /// Parse factor (multiplication, division and remainder) expression starting at current token
+    fn parse_factor(&self) -> ParseResult<Expression<'a>> {
+        let mut left = self.parse_unary()?;
+        while self.is_on(TokenType::Star) || self.is_on(TokenType::Slash) || self.is_on(TokenType::Percent) {
+            let operator = self.current_token().unwrap();
+            self.next_token();
+            let right = Box::new(Statement::Expression(self.parse_unary()?));
+            left = Expression::Binary(BinaryExpression {
+                span: left.span().to(right.span()),
+                left: Box::new(Statement::Expression(left)),
+                operator,
+                right
+            });
+        }
+        Ok(left)
     }
 
     /// Parse unary expression starting at current token
-    fn parse_unary(&self) -> Expression {
-        if self.is_on(TokenType::Exclaimation) {
-            let operator = self.current_token().unwrap().clone();
+    fn parse_unary(&self) -> ParseResult<Expression<'a>> {
+        if self.is_on(TokenType::Exclaimation) || self.is_on(TokenType::Minus) {
+            let operator = *self.current_token().unwrap();
             self.next_token();
-            return Expression::Unary(UnaryExpression {
+            let right = self.parse_unary()?;
+            return Ok(Expression::Unary(UnaryExpression {
                 operator,
-                right: Box::new(Statement::Expression(self.parse_call()))
+                span: operator.span.to(right.span()),
+                right: Box::new(Statement::Expression(right))
+            }));
+        }
+        self.parse_filter()
+    }
+
+    /// Parse filter expression starting at current token
+    fn parse_filter(&self) -> ParseResult<Expression<'a>> {
+        let mut expression = self.parse_call()?;
+        while self.is_on(TokenType::Pipe) {
+            self.next_token();
+            let name = self.expect(TokenType::Identifier)?;
+            let arguments = if self.is_on(TokenType::LeftParenthesis) {
+                self.parse_arguments()?
+            } else {
+                ArgumentList::default()
+            };
+            expression = Expression::Filter(FilterExpression {
+                span: expression.span().to(self.previous_span()),
+                value: Box::new(Statement::Expression(expression)),
+                name,
+                arguments
             });
         }
-        self.parse_call()
+        Ok(expression)
+    }
+
+    /// Parse parenthesised arguments starting at current token, named arguments must come after
+    /// positional ones
+    fn parse_arguments(&self) -> ParseResult<ArgumentList<'a>> {
+        self.expect(TokenType::LeftParenthesis)?;
+        let mut arguments = ArgumentList::default();
+        while !self.is_on(TokenType::RightParenthesis) {
+            // keywords are allowed as names, e.g. `truncate(10, end="")`
+            let is_named = self.current_token().is_some_and(|token| token.token_value.first().is_some_and(u8::is_ascii_alphabetic))
+                && self.peek_token().is_some_and(|token| token.token_type == TokenType::Equal);
+            if is_named {
+                let name = self.current_token().unwrap();
+                self.next_token();
+                self.next_token();
+                arguments.named.push((name.token_value, Statement::Expression(self.parse_expression()?)));
+            } else {
+                let argument = self.parse_expression()?;
+                if !arguments.named.is_empty() {
+                    return Err(Box::new(
+                        Diagnostic::new("E0106", "Positional argument after named argument", argument.span())
+                            .with_label("move this before the named arguments")
+                    ));
+                }
+                arguments.positional.push(Statement::Expression(argument));
+            }
+            if !self.is_on(TokenType::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+        self.expect(TokenType::RightParenthesis)?;
+        Ok(arguments)
     }
 
     /// Parse call expression starting at current token
-    fn parse_call(&self) -> Expression {
-        if self.is_on(TokenType::Identifier) {
-            let mut expression = self.parse_identifier();
-            
-            while self.current_token().expect("Unexpected end of input").token_type == TokenType::Dot {
+    fn parse_call(&self) -> ParseResult<Expression<'a>> {
+        let is_function_call = self.is_on(TokenType::Identifier)
+            && self.peek_token().is_some_and(|token| token.token_type == TokenType::LeftParenthesis);
+        let mut expression = if is_function_call {
+            let name = self.expect(TokenType::Identifier)?;
+            let arguments = self.parse_arguments()?;
+            Expression::FunctionCall(FunctionCallExpression {
+                span: name.span.to(self.previous_span()),
+                receiver: None,
+                name,
+                arguments
+            })
+        } else {
+            self.parse_primary()?
+        };
+        while self.is_on(TokenType::Dot) || self.is_on(TokenType::LeftBracket) {
+            if self.is_on(TokenType::LeftBracket) {
                 self.next_token();
-                self.on(TokenType::Identifier);
+                let index = self.parse_expression()?;
+                self.expect(TokenType::RightBracket)?;
+                expression = Expression::Index(IndexExpression {
+                    span: expression.span().to(self.previous_span()),
+                    object: Box::new(Statement::Expression(expression)),
+                    index: Box::new(Statement::Expression(index))
+                });
+                continue;
+            }
+            self.next_token();
+            if self.is_on(TokenType::Number) {
+                expression = self.parse_numeric_member(expression)?;
+                continue;
+            }
+            // keywords are allowed as member names, e.g. `what.this.is`
+            let name = match self.current_token() {
+                Some(token) if token.token_value.first().is_some_and(u8::is_ascii_alphabetic) => {
+                    self.next_token();
+                    token
+                },
+                _ => self.expect(TokenType::Identifier)?
+            };
+            if self.is_on(TokenType::LeftParenthesis) {
+                let arguments = self.parse_arguments()?;
+                expression = Expression::FunctionCall(FunctionCallExpression {
+                    span: expression.span().to(self.previous_span()),
+                    receiver: Some(Box::new(Statement::Expression(expression))),
+                    name,
+                    arguments
+                });
+            } else {
                 expression = Expression::Call(expression::CallExpression {
+                    span: expression.span().to(name.span),
                     callee: Box::new(Statement::Expression(expression)),
-                    name: self.current_token().unwrap().token_value
+                    name: name.token_value
                 });
-                self.next_token();
             }
-            return expression;
+        }
+        Ok(expression)
+    }
+
+    /// Parse numeric member access of `object` starting at current number token, e.g. `0` in
+    /// `items.0`, `0.1` is tokenized as a single number so it is split into two indices
+    /// 
+    /// # Arguments
+    /// 
+    /// * `object` - the expression being indexed
+    fn parse_numeric_member(&self, mut object: Expression<'a>) -> ParseResult<Expression<'a>> {
+        let token = self.on(TokenType::Number)?;
+        if !token.token_value.iter().all(|byte| byte.is_ascii_digit() || *byte == b'.') {
+            return Err(self.unexpected("identifier or index"));
+        }
+        self.next_token();
+        let mut start = token.span.start;
+        for part in token.token_value.split(|byte| *byte == b'.') {
+            let span = Span {
+                start,
+                end: start + part.len(),
+                column: token.span.column + (start - token.span.start),
+                ..token.span
+            };
+            start = span.end + 1;
+            let index = Expression::Literal(LiteralExpression {
+                token: Token { token_type: TokenType::Number, token_value: part, span },
+                span
+            });
+            object = Expression::Index(IndexExpression {
+                span: object.span().to(span),
+                object: Box::new(Statement::Expression(object)),
+                index: Box::new(Statement::Expression(index))
+            });
+        }
+        Ok(object)
+    }
+
+    /// Parse identifier, literal or parenthesised expression starting at current token
+    fn parse_primary(&self) -> ParseResult<Expression<'a>> {
+        if self.is_on(TokenType::Identifier) {
+            return self.parse_identifier();
+        }
+        if self.is_on(TokenType::LeftParenthesis) {
+            self.next_token();
+            let expression = self.parse_expression()?;
+            self.expect(TokenType::RightParenthesis)?;
+            return Ok(expression);
         }
         self.parse_literal()
     }
 
     /// Parse identifier expression starting at current token
-    fn parse_identifier(&self) -> Expression {
-        self.on(TokenType::Identifier);
-        let expression = Expression::Variable(expression::VariableExpression {
-            name: self.current_token().unwrap().token_value
-        });
-        self.next_token();
-        expression
+    fn parse_identifier(&self) -> ParseResult<Expression<'a>> {
+        let token = self.expect(TokenType::Identifier)?;
+        Ok(Expression::Variable(expression::VariableExpression {
+            name: token.token_value,
+            span: token.span
+        }))
     }
 
-    /// Parse literal expression starting at current token, only works for string literal currently
-    fn parse_literal(&self) -> Expression {
-        self.on(TokenType::String);
-        let token = self.current_token().unwrap().clone();
-        self.next_token();
-        Expression::Literal(expression::LiteralExpression { token })
+    /// Parse string, number, boolean or null literal expression starting at current token
+    fn parse_literal(&self) -> ParseResult<Expression<'a>> {
+        match self.current_token() {
+            Some(token) if matches!(
+                token.token_type,
+                TokenType::String | TokenType::Number | TokenType::True | TokenType::False | TokenType::Null
+            ) => {
+                self.next_token();
+                Ok(Expression::Literal(expression::LiteralExpression { token: *token, span: token.span }))
+            }
+            _ => Err(self.unexpected("expression"))
+        }
     }
 
-    /// Parse for statement starting at current token, only works for string literal currently
-    fn parse_for(&self) -> Statement {
-        self.expect(TokenType::For);
-        self.on(TokenType::Identifier);
-        let instance_identifier = self.current_token().unwrap().token_value;
-        self.next_token();
-        self.expect(TokenType::In);
-        self.on(TokenType::Identifier);
-        let array_variable = self.parse_call();
-        self.expect(TokenType::DoubleRightBrackets);
-        let statements = self.parse();
-        self.expect(TokenType::End);
-        self.expect(TokenType::DoubleRightBrackets);
-        Statement::For(statement::ForStatement{
-            instance_identifier: instance_identifier,
+    /// Parse for statement starting at current token, Option::None if header has syntax errors
+    fn parse_for(&self) -> Option<Statement<'a>> {
+        let start = self.previous_span();
+        let header = self.recover(self.parse_for_header());
+        let opening = start.to(self.previous_span());
+        *self.loop_depth.borrow_mut() += 1;
+        let statements = self.parse_block();
+        *self.loop_depth.borrow_mut() -= 1;
+        let mut else_statements: Vec<Statement> = Vec::new();
+        if self.is_on(TokenType::Else) {
+            self.next_token();
+            self.recover(self.expect(TokenType::DoubleRightBrackets));
+            else_statements = self.parse_block();
+        }
+        self.recover(self.expect_block_end(opening, "for"));
+        let (key_identifier, instance_identifier, array_variable, condition) = header?;
+        Some(Statement::For(statement::ForStatement{
+            key_identifier,
+            instance_identifier,
             array_variable: Box::new(Statement::Expression(array_variable)),
-            statements
-        })
+            condition: condition.map(|condition| Box::new(Statement::Expression(condition))),
+            statements,
+            else_statements,
+            span: start.to(self.previous_span())
+        }))
+    }
+
+    /// Parse for statement tag starting at current token, returns key or index identifier if two
+    /// identifiers are given, instance identifier, array variable and condition if given
+    fn parse_for_header(&self) -> ParseResult<ForHeader<'a>> {
+        self.expect(TokenType::For)?;
+        let mut key_identifier = None;
+        let mut instance_identifier = self.expect(TokenType::Identifier)?.token_value;
+        if self.is_on(TokenType::Comma) {
+            self.next_token();
+            key_identifier = Some(instance_identifier);
+            instance_identifier = self.expect(TokenType::Identifier)?.token_value;
+        }
+        self.expect(TokenType::In)?;
+        let array_variable = self.parse_expression()?;
+        let mut condition = None;
+        if self.is_on(TokenType::If) {
+            self.next_token();
+            condition = Some(self.parse_expression()?);
+        }
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok((key_identifier, instance_identifier, array_variable, condition))
+    }
+
+    /// Parse if statement starting at current token, Option::None if every condition has syntax errors
+    fn parse_if(&self) -> Option<Statement<'a>> {
+        let start = self.previous_span();
+        let mut branches: Vec<IfBranch> = Vec::new();
+        let condition = self.recover(self.parse_if_header());
+        let opening = start.to(self.previous_span());
+        let statements = self.parse_block();
+        if let Some(condition) = condition {
+            branches.push(IfBranch { condition: Box::new(Statement::Expression(condition)), statements, span: opening });
+        }
+        while self.is_on(TokenType::Elif)
+            || (self.is_on(TokenType::Else) && self.peek_token().is_some_and(|token| token.token_type == TokenType::If)) {
+            let branch_start = self.previous_span();
+            if self.is_on(TokenType::Else) {
+                self.next_token();
+            }
+            let condition = self.recover(self.parse_if_header());
+            let span = branch_start.to(self.previous_span());
+            let statements = self.parse_block();
+            if let Some(condition) = condition {
+                branches.push(IfBranch { condition: Box::new(Statement::Expression(condition)), statements, span });
+            }
+        }
+        let mut else_statements: Vec<Statement> = Vec::new();
+        if self.is_on(TokenType::Else) {
+            self.next_token();
+            self.recover(self.expect(TokenType::DoubleRightBrackets));
+            else_statements = self.parse_block();
+        }
+        self.recover(self.expect_block_end(opening, "if"));
+        if branches.is_empty() {
+            return None;
+        }
+        Some(Statement::If(statement::IfStatement {
+            branches,
+            else_statements,
+            span: start.to(self.previous_span())
+        }))
     }
 
-    /// Parse if statement starting at current token, only works for string literal currently
-    fn parse_if(&self) -> Statement {
-        self.expect(TokenType::If);
-        let condition = Box::new(Statement::Expression(self.parse_expression()));
-        self.expect(TokenType::DoubleRightBrackets);
-        let if_statements = self.parse();
+    /// Parse `if` or `elif` tag starting at current token, returns condition
+    fn parse_if_header(&self) -> ParseResult<Expression<'a>> {
+        if self.is_on(TokenType::Elif) {
+            self.next_token();
+        } else {
+            self.expect(TokenType::If)?;
+        }
+        let condition = self.parse_expression()?;
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok(condition)
+    }
+
+    /// Parse set statement starting at current token, Option::None if tag has syntax errors
+    fn parse_set(&self) -> Option<Statement<'a>> {
+        let start = self.previous_span();
+        let keyword = String::from_utf8_lossy(self.current_token().unwrap().token_value).into_owned();
+        let header_start = *self.i.borrow();
+        let header = self.recover(self.parse_set_header());
+        // a tag with syntax errors opens a block unless it has `=`, so its block is still parsed
+        let is_assignment = match &header {
+            Some((_, value)) => value.is_some(),
+            None => self.tokens[header_start .. *self.i.borrow()].iter().any(|token| token.token_type == TokenType::Equal)
+        };
+        if is_assignment {
+            let (name, value) = header?;
+            return Some(Statement::Set(statement::SetStatement {
+                name,
+                value: value.map(|value| Box::new(Statement::Expression(value))),
+                statements: Vec::new(),
+                span: start.to(self.previous_span())
+            }));
+        }
+        let opening = start.to(self.previous_span());
+        let statements = self.parse_block();
+        self.recover(self.expect_block_end(opening, &keyword));
+        let (name, _) = header?;
+        Some(Statement::Set(statement::SetStatement {
+            name,
+            value: None,
+            statements,
+            span: start.to(self.previous_span())
+        }))
+    }
+
+    /// Parse set statement tag starting at current token, returns name and value, value is
+    /// Option::None if the tag opens a block to capture
+    fn parse_set_header(&self) -> ParseResult<(&'a [u8], Option<Expression<'a>>)> {
+        self.next_token();
+        let name = self.expect(TokenType::Identifier)?.token_value;
+        let mut value = None;
+        if self.is_on(TokenType::Equal) {
+            self.next_token();
+            value = Some(self.parse_expression()?);
+        }
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok((name, value))
+    }
+
+    /// Parse with statement starting at current token, Option::None if header has syntax errors
+    fn parse_with(&self) -> Option<Statement<'a>> {
+        let start = self.previous_span();
+        let header = self.recover(self.parse_with_header());
+        let opening = start.to(self.previous_span());
+        let statements = self.parse_block();
+        self.recover(self.expect_block_end(opening, "with"));
+        let (value, alias) = header?;
+        Some(Statement::With(statement::WithStatement {
+            value: Box::new(Statement::Expression(value)),
+            alias,
+            statements,
+            span: start.to(self.previous_span())
+        }))
+    }
+
+    /// Parse with statement tag starting at current token, returns value and alias if given
+    fn parse_with_header(&self) -> ParseResult<(Expression<'a>, Option<&'a [u8]>)> {
+        self.expect(TokenType::With)?;
+        let value = self.parse_expression()?;
+        let mut alias = None;
+        if self.is_on(TokenType::As) {
+            self.next_token();
+            alias = Some(self.expect(TokenType::Identifier)?.token_value);
+        }
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok((value, alias))
+    }
+
+    /// Parse when statement starting at current token, Option::None if subject has syntax errors
+    fn parse_when(&self) -> Option<Statement<'a>> {
+        let start = self.previous_span();
+        let subject = self.recover(self.parse_when_header());
+        let opening = start.to(self.previous_span());
+        // only whitespace is allowed before first `is`
+        for statement in self.parse_block() {
+            if !Self::is_whitespace(&statement) {
+                self.diagnostics.borrow_mut().push(
+                    Diagnostic::new("E0105", "Unexpected statement before first `is`", statement.span())
+                        .with_label("expected `{{ is value }}`")
+                        .with_secondary_label(opening, "`when` opened here")
+                );
+            }
+        }
+        let mut arms: Vec<WhenArm> = Vec::new();
+        while self.is_on(TokenType::Is) {
+            let arm_start = self.previous_span();
+            let values = self.recover(self.parse_when_arm_header());
+            let span = arm_start.to(self.previous_span());
+            let statements = self.parse_block();
+            if let Some(values) = values {
+                arms.push(WhenArm { values, statements, span });
+            }
+        }
         let mut else_statements: Vec<Statement> = Vec::new();
         if self.is_on(TokenType::Else) {
             self.next_token();
-            self.expect(TokenType::DoubleRightBrackets);
-            else_statements = self.parse();
+            self.recover(self.expect(TokenType::DoubleRightBrackets));
+            else_statements = self.parse_block();
+        }
+        self.recover(self.expect_block_end(opening, "when"));
+        Some(Statement::When(statement::WhenStatement {
+            subject: Box::new(Statement::Expression(subject?)),
+            arms,
+            else_statements,
+            span: start.to(self.previous_span())
+        }))
+    }
+
+    /// Parse when statement tag starting at current token, returns subject
+    fn parse_when_header(&self) -> ParseResult<Expression<'a>> {
+        self.expect(TokenType::When)?;
+        let subject = self.parse_expression()?;
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok(subject)
+    }
+
+    /// Parse `is` tag of when statement starting at current token, returns values to match
+    fn parse_when_arm_header(&self) -> ParseResult<Vec<Statement<'a>>> {
+        self.expect(TokenType::Is)?;
+        let mut values = vec![Statement::Expression(self.parse_expression()?)];
+        while self.is_on(TokenType::Comma) {
+            self.next_token();
+            values.push(Statement::Expression(self.parse_expression()?));
+        }
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok(values)
+    }
+
+    /// Returns true if `statement` is template text containing only whitespace
+    /// 
+    /// # Arguments
+    /// 
+    /// * `statement` - the statement to check
+    fn is_whitespace(statement: &Statement) -> bool {
+        match statement {
+            Statement::Expression(Expression::TemplateLiteral(template_literal)) => {
+                template_literal.value.iter().all(|byte| byte.is_ascii_whitespace())
+            }
+            _ => false
         }
-        self.expect(TokenType::End);
-        self.expect(TokenType::DoubleRightBrackets);
-        Statement::If(statement::IfStatement {
-            condition,
-            if_statements,
-            else_statements
-        })
+    }
+
+    /// Parse `end }}` of block opened by `opening` starting at current token
+    /// 
+    /// # Arguments
+    /// 
+    /// * `opening` - span of the tag that opened the block
+    /// * `keyword` - keyword of the block, e.g. `for`
+    fn expect_block_end(&self, opening: Span, keyword: &str) -> ParseResult<()> {
+        self.expect_end(opening, keyword)?;
+        self.expect(TokenType::DoubleRightBrackets)?;
+        Ok(())
     }
 }
 
@@ -264,7 +896,21 @@ impl<'a, 'b> Parser<'a> {
 mod tests {
     // use crate::expression::VariableExpression;
 
-    // use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    use super::*;
+
+    #[test]
+    fn reports_all_errors() {
+        let source = "{{ for }}a{{ end }}{{ name name }}b{{ if x }}{{ x. }}{{ end }}{{ end }}{{ y }}{{ if z }}";
+        let tokens = Tokenizer::new(source.as_bytes()).tokenize().unwrap();
+        let (statements, diagnostics) = Parser::new(&tokens).parse();
+        let codes: Vec<(&str, usize)> = diagnostics.iter().map(|diagnostic| (diagnostic.code, diagnostic.label.span.start)).collect();
+        assert_eq!(codes, vec![("E0102", 7), ("E0102", 27), ("E0102", 51), ("E0104", 65), ("E0103", 88)]);
+        // `b`, `if x` and `y` are kept, `if z` is kept with empty body
+        assert_eq!(statements.len(), 4);
+    }
+
 
     // #[test]
     // fn it_works() {
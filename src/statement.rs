@@ -1,26 +1,109 @@
-use crate::expression::{self};
+use crate::{expression::{self}, Span};
 
 /// Represents an AST for a statement
 #[derive(Debug)]
 pub enum Statement<'a> {
     Expression(expression::Expression<'a>),
     For(ForStatement<'a>),
-    If(IfStatement<'a>)
+    If(IfStatement<'a>),
+    When(WhenStatement<'a>),
+    Set(SetStatement<'a>),
+    With(WithStatement<'a>),
+    Break(BreakStatement),
+    Continue(ContinueStatement)
+}
+
+impl<'a> Statement<'a> {
+    /// Returns the location of the statement in source
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Expression(expression) => expression.span(),
+            Statement::For(statement) => statement.span,
+            Statement::If(statement) => statement.span,
+            Statement::When(statement) => statement.span,
+            Statement::Set(statement) => statement.span,
+            Statement::With(statement) => statement.span,
+            Statement::Break(statement) => statement.span,
+            Statement::Continue(statement) => statement.span
+        }
+    }
 }
 
 
 /// Represents an AST for for statement
 #[derive(Debug)]
 pub struct ForStatement<'a> {
+    pub key_identifier: Option<&'a [u8]>,
     pub instance_identifier: &'a [u8],
     pub array_variable: Box<Statement<'a>>,
-    pub statements: Vec<Statement<'a>>
+    pub condition: Option<Box<Statement<'a>>>,
+    pub statements: Vec<Statement<'a>>,
+    pub else_statements: Vec<Statement<'a>>,
+    pub span: Span
 }
 
 /// Represents an AST for if statement
 #[derive(Debug)]
 pub struct IfStatement<'a> {
+    /// the `if` branch followed by every `else if` branch, in order
+    pub branches: Vec<IfBranch<'a>>,
+    pub else_statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for a branch of if statement, rendered if `condition` is truthy
+#[derive(Debug)]
+pub struct IfBranch<'a> {
     pub condition: Box<Statement<'a>>,
-    pub if_statements: Vec<Statement<'a>>,
-    pub else_statements: Vec<Statement<'a>>
-}
\ No newline at end of file
+    pub statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for when statement
+#[derive(Debug)]
+pub struct WhenStatement<'a> {
+    pub subject: Box<Statement<'a>>,
+    pub arms: Vec<WhenArm<'a>>,
+    pub else_statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for an `is` arm of when statement, matches if subject equals any of `values`
+#[derive(Debug)]
+pub struct WhenArm<'a> {
+    pub values: Vec<Statement<'a>>,
+    pub statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for set statement, `value` is Option::None if the rendered `statements` are
+/// captured instead
+#[derive(Debug)]
+pub struct SetStatement<'a> {
+    pub name: &'a [u8],
+    pub value: Option<Box<Statement<'a>>>,
+    pub statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for with statement, `alias` is Option::None if the object `value` evaluates
+/// to is the scope itself
+#[derive(Debug)]
+pub struct WithStatement<'a> {
+    pub value: Box<Statement<'a>>,
+    pub alias: Option<&'a [u8]>,
+    pub statements: Vec<Statement<'a>>,
+    pub span: Span
+}
+
+/// Represents an AST for break statement
+#[derive(Debug)]
+pub struct BreakStatement {
+    pub span: Span
+}
+
+/// Represents an AST for continue statement
+#[derive(Debug)]
+pub struct ContinueStatement {
+    pub span: Span
+}
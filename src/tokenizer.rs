@@ -1,28 +1,76 @@
 use std::cell::RefCell;
 
-use crate::{Token, TokenType, message_formatter};
+use crate::{Token, TokenType, Span, Options, error::RenderError, message_formatter::Diagnostic};
+
+/// Whitespace to be removed from start of the template text after a tag
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum Trim {
+    Nothing,
+    /// all whitespace, after `-}}`
+    Whitespace,
+    /// a single newline, after a block tag when `trim_blocks` is set
+    Newline
+}
 
 /// Tokenizes given source code
 pub struct Tokenizer<'a> {
     /// current index
     i: RefCell<usize>,
     token_start: RefCell<usize>,
+    /// line and column of `token_start`
+    token_position: RefCell<(usize, usize)>,
+    /// current line number, starting at 1
+    line: RefCell<usize>,
+    /// index of first byte of current line
+    line_start: RefCell<usize>,
+    /// index and column of the last position returned by `get_position`, so columns on long
+    /// lines are counted only once
+    column_cache: RefCell<(usize, usize)>,
     /// source code
     source: &'a [u8],
     alphabetic_token_map: std::collections::HashMap<&'static [u8], TokenType>,
-    in_curly: RefCell<bool>
+    in_curly: RefCell<bool>,
+    /// if true comments are returned as `TokenType::Comment` tokens, else they are skipped
+    keep_comments: bool,
+    /// if true the first newline after a block tag is removed
+    trim_blocks: bool,
+    /// if true spaces and tabs from start of line up to a block tag are removed
+    lstrip_blocks: bool,
+    /// true if tag tokenizer is currently in is a block tag, e.g. `{{ for x in y }}`
+    in_block_tag: RefCell<bool>,
+    /// whitespace to be removed from start of next template text
+    trim_next: RefCell<Trim>
 }
 
 impl<'a> Tokenizer<'a> {
-    const TOKEN_MAP: [(&[u8], TokenType); 8] = [
+    const TOKEN_MAP: [(&'static [u8], TokenType); 27] = [
         ("{{".as_bytes(), TokenType::DoubleLeftBrackets),
         ("}}".as_bytes(), TokenType::DoubleRightBrackets),
+        ("-}}".as_bytes(), TokenType::DoubleRightBrackets),
+        ("..=".as_bytes(), TokenType::DoubleDotEqual),
+        ("..".as_bytes(), TokenType::DoubleDot),
         (".".as_bytes(), TokenType::Dot),
+        (",".as_bytes(), TokenType::Comma),
         ("==".as_bytes(), TokenType::DoubleEquals),
         ("!=".as_bytes(), TokenType::ExclaimationEqual),
+        ("<=".as_bytes(), TokenType::LessEqual),
+        ("<".as_bytes(), TokenType::Less),
+        (">=".as_bytes(), TokenType::GreaterEqual),
+        (">".as_bytes(), TokenType::Greater),
         ("!".as_bytes(), TokenType::Exclaimation),
+        ("-".as_bytes(), TokenType::Minus),
+        ("+".as_bytes(), TokenType::Plus),
+        ("*".as_bytes(), TokenType::Star),
+        ("/".as_bytes(), TokenType::Slash),
+        ("%".as_bytes(), TokenType::Percent),
+        ("(".as_bytes(), TokenType::LeftParenthesis),
+        (")".as_bytes(), TokenType::RightParenthesis),
+        ("[".as_bytes(), TokenType::LeftBracket),
+        ("]".as_bytes(), TokenType::RightBracket),
         ("&&".as_bytes(), TokenType::DoubleAmpersand),
-        ("||".as_bytes(), TokenType::DoublePipe)
+        ("||".as_bytes(), TokenType::DoublePipe),
+        ("|".as_bytes(), TokenType::Pipe),
+        ("=".as_bytes(), TokenType::Equal)
     ];
 
     /// Returns a tokenizer used to tokenize `source`
@@ -34,28 +82,68 @@ impl<'a> Tokenizer<'a> {
         Self {
             i: RefCell::new(0),
             token_start: RefCell::new(0),
-            source: source,
+            token_position: RefCell::new((1, 1)),
+            line: RefCell::new(1),
+            line_start: RefCell::new(0),
+            column_cache: RefCell::new((0, 1)),
+            source,
             alphabetic_token_map: std::collections::HashMap::from([
                 ("for".as_bytes(), TokenType::For),
                 ("in".as_bytes(), TokenType::In),
                 ("when".as_bytes(), TokenType::When),
+                ("is".as_bytes(), TokenType::Is),
+                ("break".as_bytes(), TokenType::Break),
+                ("continue".as_bytes(), TokenType::Continue),
+                ("set".as_bytes(), TokenType::Set),
+                ("let".as_bytes(), TokenType::Let),
+                ("with".as_bytes(), TokenType::With),
+                ("as".as_bytes(), TokenType::As),
                 ("if".as_bytes(), TokenType::If),
                 ("else".as_bytes(), TokenType::Else),
+                ("elif".as_bytes(), TokenType::Elif),
                 ("end".as_bytes(), TokenType::End),
+                ("true".as_bytes(), TokenType::True),
+                ("false".as_bytes(), TokenType::False),
+                ("null".as_bytes(), TokenType::Null),
             ]),
-            in_curly: RefCell::new(false)
+            in_curly: RefCell::new(false),
+            keep_comments: false,
+            trim_blocks: false,
+            lstrip_blocks: false,
+            in_block_tag: RefCell::new(false),
+            trim_next: RefCell::new(Trim::Nothing)
         }
     }
 
-    /// Returns tokens of refrenced text
-    pub fn tokenize(&self) -> Vec<Token> {
+    /// Returns the tokenizer with whitespace around block tags removed as set in `options`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `options` - options with `trim_blocks` and `lstrip_blocks` to use
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.trim_blocks = options.trim_blocks;
+        self.lstrip_blocks = options.lstrip_blocks;
+        self
+    }
+
+    /// Returns the tokenizer returning comments as `TokenType::Comment` tokens instead of skipping
+    /// them, for tools like formatters that need to preserve comments, the parser does not accept them
+    /// 
+    /// # Arguments
+    /// 
+    /// * `keep_comments` - if true comments are returned as tokens
+    pub fn with_comments(mut self, keep_comments: bool) -> Self {
+        self.keep_comments = keep_comments;
+        self
+    }
+
+    /// Returns tokens of refrenced text, or `RenderError::Lex` if text contains an invalid token
+    pub fn tokenize(&self) -> Result<Vec<Token<'a>>, RenderError> {
         let mut tokens: Vec<Token> = Vec::new();
-        let mut token = self.next();
-        while token != None {
-            tokens.push(token.unwrap());
-            token = self.next();
+        while let Some(token) = self.next()? {
+            tokens.push(token);
         }
-        tokens
+        Ok(tokens)
     }
 
     /// Returns `Option::Some` with current `u8`, if at EOF returns `Option::None`
@@ -68,10 +156,42 @@ impl<'a> Tokenizer<'a> {
 
     /// Increments parser to next `u8` and retuens Option::Some with it, if at EOF returns `Option::None`
     fn increment(&self) -> Option<u8> {
-        let new = *self.i.borrow() + 1;
-        self.i.replace(new);
+        let current = *self.i.borrow();
+        if self.source.get(current) == Some(&b'\n') {
+            let line = *self.line.borrow() + 1;
+            self.line.replace(line);
+            self.line_start.replace(current + 1);
+        }
+        self.i.replace(current + 1);
         self.get_current()
     }
+
+    /// Returns line and column of current index, column counts UTF-8 characters
+    fn get_position(&self) -> (usize, usize) {
+        let line_start = *self.line_start.borrow();
+        let current = *self.i.borrow();
+        let (mut start, mut column) = *self.column_cache.borrow();
+        // count from start of line if cache is on a previous line or after current index
+        if start < line_start || start > current {
+            (start, column) = (line_start, 1);
+        }
+        column += self.source[start .. current]
+            .iter()
+            .filter(|byte| (**byte & 0xC0) != 0x80)
+            .count();
+        self.column_cache.replace((current, column));
+        (*self.line.borrow(), column)
+    }
+
+    /// Returns span starting at `token_start` and ending at `end`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `end` - byte offset after last byte of span
+    fn get_span(&self, end: usize) -> Span {
+        let (line, column) = *self.token_position.borrow();
+        Span { start: *self.token_start.borrow(), end, line, column }
+    }
     
     /// Returns true if currently on `characters` starting at current index else false
     /// 
@@ -79,17 +199,7 @@ impl<'a> Tokenizer<'a> {
     /// 
     /// * `characters` - the characters to check for
     fn is_on(&self, characters: &[u8]) -> bool {
-        let mut i = self.i.borrow().clone();
-        for character in characters {
-            if i >= self.source.len() {
-                return false;
-            }
-            if self.source[i] != *character {
-                return false;
-            }
-            i += 1;
-        }
-        return true;
+        self.source[*self.i.borrow()..].starts_with(characters)
     }
 
     /// Returns true if last index in parser was equal to `character`
@@ -99,17 +209,11 @@ impl<'a> Tokenizer<'a> {
     /// * `character` - the character to be checked
     fn is_previous(&self, character: u8) -> bool {
         let current = *self.i.borrow();
-        if current == 0 {
-            false
-        } else if self.source[current - 1] == character {
-            true
-        } else {
-            false
-        }
+        current != 0 && self.source[current - 1] == character
     }
     
     /// Returns text of token parser is currently on, ends at current index
-    fn get_last_token(&self) -> &[u8] {
+    fn get_last_token(&self) -> &'a [u8] {
         &self.source[*self.token_start.borrow() .. *self.i.borrow()]
     }
 
@@ -117,26 +221,255 @@ impl<'a> Tokenizer<'a> {
     /// 
     /// # Arguments
     /// * `token_type` - the type of the `Token` to be returned
-    fn tokenize_last(&self, type_type: TokenType) -> Token {
+    fn tokenize_last(&self, type_type: TokenType) -> Token<'a> {
         Token {
             token_type: type_type,
-            token_value: self.get_last_token()
+            token_value: self.get_last_token(),
+            span: self.get_span(*self.i.borrow())
         }
     }
     
-    /// Tokenizes template string parser is currently on
-    fn tokenize_template_string(&self) -> Token {
+    /// Tokenizes template string parser is currently on, trailing whitespace is removed before
+    /// `{{-` and before block tags when `lstrip_blocks` is set, returns `Option::None` if nothing is left
+    fn tokenize_template_string(&self) -> Option<Token<'a>> {
         loop {
             match self.increment() {
                 None => {
-                    return self.tokenize_last(TokenType::TempalteLiteral)
+                    return Some(self.tokenize_last(TokenType::TempalteLiteral))
                 },
                 Some(_) => {
                     if self.is_on("{{".as_bytes()) {
-                        return self.tokenize_last(TokenType::TempalteLiteral)
+                        return self.tokenize_trimmed_template_string();
+                    }
+                    if self.is_on("\\\\{{".as_bytes()) {
+                        return Some(self.tokenize_escaped_backslash());
                     }
+                    if self.is_on("{#".as_bytes()) || self.is_on("\\{{".as_bytes()) {
+                        return Some(self.tokenize_last(TokenType::TempalteLiteral))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tokenizes template string up to and including the first backslash of `\\{{` parser is
+    /// currently on, the second backslash is skipped so `{{` starts a tag
+    fn tokenize_escaped_backslash(&self) -> Token<'a> {
+        self.increment();
+        let token = self.tokenize_last(TokenType::TempalteLiteral);
+        self.increment();
+        token
+    }
+
+    /// Tokenizes template string ending at tag parser is currently on, with whitespace removed
+    /// as requested by the tag, returns `Option::None` if nothing is left
+    fn tokenize_trimmed_template_string(&self) -> Option<Token<'a>> {
+        let start = *self.token_start.borrow();
+        let end = self.trimmed_end(start, *self.i.borrow());
+        if end == start {
+            return None;
+        }
+        Some(Token {
+            token_type: TokenType::TempalteLiteral,
+            token_value: &self.source[start .. end],
+            span: self.get_span(end)
+        })
+    }
+
+    /// Returns end of template text starting at `start` and followed by tag at `tag_start`, with
+    /// whitespace before the tag removed if it starts with `{{-` or is a block tag indented with
+    /// spaces and tabs when `lstrip_blocks` is set
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - index of first byte of template text
+    /// * `tag_start` - index of `{{` of the tag after the template text
+    fn trimmed_end(&self, start: usize, tag_start: usize) -> usize {
+        let mut end = tag_start;
+        if self.source[tag_start..].starts_with("{{-".as_bytes()) {
+            while end > start && self.source[end - 1].is_ascii_whitespace() {
+                end -= 1;
+            }
+        } else if self.lstrip_blocks && self.is_block_tag(tag_start) {
+            let mut line_start = end;
+            while line_start > 0 && matches!(self.source[line_start - 1], b' ' | b'\t') {
+                line_start -= 1;
+            }
+            // only remove indentation, not whitespace after other text on the same line
+            if line_start >= start && (line_start == 0 || self.source[line_start - 1] == b'\n') {
+                end = line_start;
+            }
+        }
+        end
+    }
+
+    /// Returns whitespace to be removed after a tag
+    /// 
+    /// # Arguments
+    /// 
+    /// * `trim_marker` - true if the tag ends with `-}}`
+    /// * `block_tag` - true if the tag is a block tag
+    fn trim_after(&self, trim_marker: bool, block_tag: bool) -> Trim {
+        if trim_marker {
+            Trim::Whitespace
+        } else if self.trim_blocks && block_tag {
+            Trim::Newline
+        } else {
+            Trim::Nothing
+        }
+    }
+
+    /// Returns true if tag starting at `tag_start` is a block tag, i.e. `{{ raw }}`, `{{ endraw }}`
+    /// or starts with a keyword of a statement that does not output a value like `for`, `if` or `end`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_start` - index of `{{`
+    fn is_block_tag(&self, tag_start: usize) -> bool {
+        if self.tag_end(tag_start, "raw".as_bytes()).is_some() || self.tag_end(tag_start, "endraw".as_bytes()).is_some() {
+            return true;
+        }
+        let mut start = tag_start + 2;
+        while self.source.get(start).is_some_and(|character| *character == b'-' || character.is_ascii_whitespace()) {
+            start += 1;
+        }
+        let mut end = start;
+        while self.source.get(end).is_some_and(|character| *character == b'_' || character.is_ascii_alphanumeric()) {
+            end += 1;
+        }
+        matches!(
+            self.get_symbol_token_type(&self.source[start .. end]),
+            TokenType::For | TokenType::If | TokenType::Elif | TokenType::Else | TokenType::End
+                | TokenType::When | TokenType::Is | TokenType::Set | TokenType::Let | TokenType::With
+                | TokenType::Break | TokenType::Continue
+        )
+    }
+
+    /// Increments parser past whitespace to be removed after the previous tag
+    fn skip_trimmed(&self) {
+        match self.trim_next.replace(Trim::Nothing) {
+            Trim::Nothing => {},
+            Trim::Whitespace => {
+                while self.get_current().is_some_and(|character| character.is_ascii_whitespace()) {
+                    self.increment();
+                }
+            },
+            Trim::Newline => {
+                if self.is_on("\r\n".as_bytes()) {
+                    self.increment();
                 }
+                if self.is_on("\n".as_bytes()) {
+                    self.increment();
+                }
+            }
+        }
+    }
+
+    /// Increments parser until current index is `end`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `end` - the index to stop at
+    fn skip_to(&self, end: usize) {
+        while *self.i.borrow() < end {
+            self.increment();
+        }
+    }
+
+    /// Returns index after tag and true if it ends with `-}}` if `{{ name }}` starts at `start`,
+    /// whitespace inside the curly brackets and trim markers are optional, else `Option::None`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - index to check for the tag
+    /// * `name` - the only symbol inside the tag
+    fn tag_end(&self, start: usize, name: &[u8]) -> Option<(usize, bool)> {
+        let skip_whitespace = |mut index: usize| {
+            while self.source.get(index).is_some_and(|character| character.is_ascii_whitespace()) {
+                index += 1;
             }
+            index
+        };
+        let rest = self.source.get(start..)?;
+        if !rest.starts_with("{{".as_bytes()) {
+            return None;
+        }
+        let mut name_start = start + 2;
+        if self.source.get(name_start) == Some(&b'-') {
+            name_start += 1;
+        }
+        let name_start = skip_whitespace(name_start);
+        if !self.source[name_start..].starts_with(name) {
+            return None;
+        }
+        let close = skip_whitespace(name_start + name.len());
+        if self.source[close..].starts_with("-}}".as_bytes()) {
+            return Some((close + 3, true));
+        }
+        self.source[close..].starts_with("}}".as_bytes()).then_some((close + 2, false))
+    }
+
+    /// Tokenizes raw block parser is currently on, the text between `{{ raw }}` and the next
+    /// `{{ endraw }}` is returned as template text without looking for tags or comments, whitespace
+    /// around the text is removed like around other block tags
+    /// 
+    /// # Arguments
+    /// 
+    /// * `content_start` - index after `{{ raw }}`
+    /// * `trim_marker` - true if `{{ raw }}` ends with `-}}`
+    fn tokenize_raw(&self, content_start: usize, trim_marker: bool) -> Result<Token<'a>, RenderError> {
+        let opening = self.get_span(content_start);
+        self.skip_to(content_start);
+        self.trim_next.replace(self.trim_after(trim_marker, true));
+        self.skip_trimmed();
+        self.token_start.replace(*self.i.borrow());
+        self.token_position.replace(self.get_position());
+        loop {
+            let endraw_end = self.tag_end(*self.i.borrow(), "endraw".as_bytes());
+            if let Some((end, trim_marker)) = endraw_end {
+                let start = *self.token_start.borrow();
+                let content_end = self.trimmed_end(start, *self.i.borrow());
+                let token = Token {
+                    token_type: TokenType::TempalteLiteral,
+                    token_value: &self.source[start .. content_end],
+                    span: self.get_span(content_end)
+                };
+                self.skip_to(end);
+                self.trim_next.replace(self.trim_after(trim_marker, true));
+                return Ok(token);
+            }
+            if self.get_current().is_none() {
+                return Err(RenderError::Lex(Box::new(
+                    Diagnostic::new("E0004", "Unterminated raw block", opening)
+                        .with_label("raw block starts here")
+                        .with_help("close the raw block with `{{ endraw }}`")
+                )));
+            }
+            self.increment();
+        }
+    }
+
+    /// Tokenizes comment parser is currently on, from `{#` up to and including the next `#}`,
+    /// comments can span multiple lines and contain `{{`
+    fn tokenize_comment(&self) -> Result<Token<'a>, RenderError> {
+        // skip `{#`
+        self.increment();
+        self.increment();
+        loop {
+            if self.is_on("#}".as_bytes()) {
+                self.increment();
+                self.increment();
+                return Ok(self.tokenize_last(TokenType::Comment));
+            }
+            if self.get_current().is_none() {
+                let opening = self.get_span(*self.token_start.borrow() + 2);
+                return Err(RenderError::Lex(Box::new(
+                    Diagnostic::new("E0003", "Unterminated comment", opening)
+                        .with_label("comment starts here")
+                        .with_help("close the comment with `#}`")
+                )));
+            }
+            self.increment();
         }
     }
 
@@ -146,11 +479,46 @@ impl<'a> Tokenizer<'a> {
     /// 
     /// * `symbol` - alphabetic symbol to get `TokenType` for
     fn get_symbol_token_type(&self, symbol: &[u8]) -> TokenType {
-        self.alphabetic_token_map.get(symbol).unwrap_or(&TokenType::Identifier).clone()
+        *self.alphabetic_token_map.get(symbol).unwrap_or(&TokenType::Identifier)
+    }
+
+    /// Increments parser past consecutive ASCII digits, returns true if there was at least one
+    fn skip_digits(&self) -> bool {
+        let mut skipped = false;
+        while self.get_current().is_some_and(|character| character.is_ascii_digit()) {
+            self.increment();
+            skipped = true;
+        }
+        skipped
+    }
+
+    /// Tokenizes number literal parser is currently on, with optional fraction and exponent,
+    /// e.g. `42`, `0.5`, `1e-3`
+    fn tokenize_number(&self) -> Token<'a> {
+        self.skip_digits();
+        let fraction_start = *self.i.borrow();
+        if self.get_current() == Some(b'.') {
+            self.increment();
+            // `.` not followed by a digit is not part of the number
+            if !self.skip_digits() {
+                self.i.replace(fraction_start);
+            }
+        }
+        let exponent_start = *self.i.borrow();
+        if matches!(self.get_current(), Some(b'e') | Some(b'E')) {
+            if matches!(self.increment(), Some(b'+') | Some(b'-')) {
+                self.increment();
+            }
+            // `e` not followed by digits is not part of the number
+            if !self.skip_digits() {
+                self.i.replace(exponent_start);
+            }
+        }
+        self.tokenize_last(TokenType::Number)
     }
 
     /// Tokenizes alphabetic symbol parser is currently on
-    fn tokenize_symbol(&self) -> Token {
+    fn tokenize_symbol(&self) -> Token<'a> {
         loop {
             match self.increment() {
                 None => {
@@ -166,63 +534,107 @@ impl<'a> Tokenizer<'a> {
     }
 
     /// Tokenizes string literal parser is currently on, double quotes (") in string can be escaped with backslash (\)
-    fn tokenize_string_literal(&self) -> Token {
+    fn tokenize_string_literal(&self) -> Result<Token<'a>, RenderError> {
         loop {
             match self.increment() {
                 None => {
-                    panic!("Unexpected end of input");
+                    let quote = self.get_span(*self.token_start.borrow() + 1);
+                    return Err(RenderError::Lex(Box::new(
+                        Diagnostic::new("E0002", "Unterminated string literal", quote)
+                            .with_label("string literal starts here")
+                            .with_help("close the string literal with `\"`")
+                    )));
                 }
                 Some(character) => {
                     if !self.is_previous(b'\\') && character == b'"' {
                         // eat quotes
                         self.increment();
-                        return self.tokenize_last(TokenType::String);
+                        return Ok(self.tokenize_last(TokenType::String));
                     }
                 }
             }
         }
     }
 
-    /// Returns Option::Some with next token in refrenced `[u8]`, or Option::None if at end
-    pub fn next(&self) -> Option<Token> {
+    /// Returns Option::Some with next token in refrenced `[u8]`, or Option::None if at end,
+    /// `RenderError::Lex` if an invalid character is encountered
+    pub fn next(&self) -> Result<Option<Token<'a>>, RenderError> {
         loop {
             self.token_start.replace(*self.i.borrow());
+            self.token_position.replace(self.get_position());
             match self.get_current() {
                 Some(character) => {
                     if !*self.in_curly.borrow() {
-                        if self.is_on("{{".as_bytes()) {
+                        if *self.trim_next.borrow() != Trim::Nothing {
+                            self.skip_trimmed();
+                            continue;
+                        }
+                        let raw_end = self.tag_end(*self.i.borrow(), "raw".as_bytes());
+                        if let Some((end, trim_marker)) = raw_end {
+                            return self.tokenize_raw(end, trim_marker).map(Some);
+                        } else if self.is_on("\\\\{{".as_bytes()) {
+                            return Ok(Some(self.tokenize_escaped_backslash()));
+                        } else if self.is_on("\\{{".as_bytes()) {
+                            // skip backslash, escaped curly brackets start template text
+                            self.increment();
+                            self.token_start.replace(*self.i.borrow());
+                            self.token_position.replace(self.get_position());
+                            self.increment();
+                            return Ok(self.tokenize_template_string());
+                        } else if self.is_on("{{".as_bytes()) {
                             self.in_curly.replace(true);
-                            // skip curly
+                            let block_tag = self.is_block_tag(*self.i.borrow());
+                            self.in_block_tag.replace(block_tag);
+                            // skip curly and trim marker
                             self.increment();
                             self.increment();
-                            return Some(self.tokenize_last(TokenType::DoubleLeftBrackets));
-                        } else {
-                            return Some(self.tokenize_template_string());
+                            if self.get_current() == Some(b'-') {
+                                self.increment();
+                            }
+                            return Ok(Some(self.tokenize_last(TokenType::DoubleLeftBrackets)));
+                        } else if self.is_on("{#".as_bytes()) {
+                            let comment = self.tokenize_comment()?;
+                            if self.keep_comments {
+                                return Ok(Some(comment));
+                            }
+                        } else if let Some(token) = self.tokenize_template_string() {
+                            return Ok(Some(token));
                         }
+                    } else if character.is_ascii_digit() {
+                        return Ok(Some(self.tokenize_number()));
+                    } else if character.is_ascii_alphabetic() {
+                        return Ok(Some(self.tokenize_symbol()));
+                    } else if character == b'"' {
+                        return self.tokenize_string_literal().map(Some);
+                    } else if character.is_ascii_whitespace() {
+                        self.increment();
                     } else {
-                        if character.is_ascii_alphabetic() {
-                            return Some(self.tokenize_symbol());
-                        } else if character == b'"' {
-                            return Some(self.tokenize_string_literal());
-                        } else if character.is_ascii_whitespace() {
-                            self.increment();
-                        } else {
-                            for entry in Self::TOKEN_MAP {
-                                if self.is_on(entry.0) {
-                                    let new = *self.i.borrow() + entry.0.len();
-                                    self.i.replace(new);
-                                    if entry.1 == TokenType::DoubleRightBrackets {
-                                        self.in_curly.replace(false);
-                                    }
-                                    return Some(self.tokenize_last(entry.1));
+                        for entry in Self::TOKEN_MAP {
+                            if self.is_on(entry.0) {
+                                let new = *self.i.borrow() + entry.0.len();
+                                self.i.replace(new);
+                                if entry.1 == TokenType::DoubleRightBrackets {
+                                    self.in_curly.replace(false);
+                                    let trim = self.trim_after(entry.0.starts_with("-".as_bytes()), *self.in_block_tag.borrow());
+                                    self.trim_next.replace(trim);
                                 }
+                                return Ok(Some(self.tokenize_last(entry.1)));
                             }
-                            panic!("{}", message_formatter::format(&self.source, *self.i.borrow(), "Invalid character"));
                         }
+                        // span the whole UTF-8 character
+                        let mut end = *self.i.borrow() + 1;
+                        while end < self.source.len() && (self.source[end] & 0xC0) == 0x80 {
+                            end += 1;
+                        }
+                        let character = String::from_utf8_lossy(&self.source[*self.i.borrow() .. end]);
+                        return Err(RenderError::Lex(Box::new(
+                            Diagnostic::new("E0001", "Invalid character", self.get_span(end))
+                                .with_label(format!("`{}` is not valid inside `{{{{ }}}}`", character))
+                        )));
                     }
                 },
                 None => {
-                    return None;
+                    return Ok(None);
                 }
             }
         }
@@ -240,39 +652,148 @@ mod tests {
             // r#"hello, {{ name }} yes man {{ "no man" }}{{ for item in items }}{{ if property"#
             r#"hello, {{    person.name}} y {{yes}}{{for item in items}}  a {{ "yes" }} {{ if property == "yes" && property || property }}"#.as_bytes()
         );
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::TempalteLiteral, token_value: "hello, ".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleLeftBrackets, token_value: "{{".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "person".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Dot, token_value: ".".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "name".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleRightBrackets, token_value: "}}".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::TempalteLiteral, token_value: " y ".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleLeftBrackets, token_value: "{{".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "yes".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleRightBrackets, token_value: "}}".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleLeftBrackets, token_value: "{{".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::For, token_value: "for".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "item".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::In, token_value: "in".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "items".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleRightBrackets, token_value: "}}".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::TempalteLiteral, token_value: "  a ".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleLeftBrackets, token_value: "{{".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::String, token_value: "\"yes\"".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleRightBrackets, token_value: "}}".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::TempalteLiteral, token_value: " ".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleLeftBrackets, token_value: "{{".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::If, token_value: "if".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "property".as_bytes()});
-
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleEquals, token_value: "==".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::String, token_value: "\"yes\"".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleAmpersand, token_value: "&&".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "property".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoublePipe, token_value: "||".as_bytes()});
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::Identifier, token_value: "property".as_bytes()});
-
-        assert_eq!(tokener.next().unwrap(), Token{token_type: TokenType::DoubleRightBrackets, token_value: "}}".as_bytes()});
-        assert_eq!(tokener.next(), None);
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::TempalteLiteral, "hello, ".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleLeftBrackets, "{{".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "person".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Dot, ".".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "name".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleRightBrackets, "}}".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::TempalteLiteral, " y ".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleLeftBrackets, "{{".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "yes".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleRightBrackets, "}}".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleLeftBrackets, "{{".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::For, "for".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "item".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::In, "in".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "items".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleRightBrackets, "}}".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::TempalteLiteral, "  a ".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleLeftBrackets, "{{".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::String, "\"yes\"".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleRightBrackets, "}}".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::TempalteLiteral, " ".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleLeftBrackets, "{{".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::If, "if".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "property".as_bytes())));
+
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleEquals, "==".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::String, "\"yes\"".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleAmpersand, "&&".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "property".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoublePipe, "||".as_bytes())));
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::Identifier, "property".as_bytes())));
+
+        assert_eq!(tokener.next().unwrap().map(|token| (token.token_type, token.token_value)), Some((TokenType::DoubleRightBrackets, "}}".as_bytes())));
+        assert_eq!(tokener.next(), Ok(None));
+    }
+
+    #[test]
+    fn numbers() {
+        let tokens = Tokenizer::new("{{ 42 0.5 1e3 2.5E-3 -7 1.e }}".as_bytes()).tokenize().unwrap();
+        let tokens: Vec<(TokenType, &[u8])> = tokens.iter().map(|token| (token.token_type, token.token_value)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::DoubleLeftBrackets, "{{".as_bytes()),
+            (TokenType::Number, "42".as_bytes()),
+            (TokenType::Number, "0.5".as_bytes()),
+            (TokenType::Number, "1e3".as_bytes()),
+            (TokenType::Number, "2.5E-3".as_bytes()),
+            (TokenType::Minus, "-".as_bytes()),
+            (TokenType::Number, "7".as_bytes()),
+            (TokenType::Number, "1".as_bytes()),
+            (TokenType::Dot, ".".as_bytes()),
+            (TokenType::Identifier, "e".as_bytes()),
+            (TokenType::DoubleRightBrackets, "}}".as_bytes())
+        ]);
+    }
+
+    #[test]
+    fn invalid_character() {
+        let tokener = Tokenizer::new("a {{ name # }}".as_bytes());
+        let error = tokener.tokenize().unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 10, end: 11, line: 1, column: 11 }));
+        assert_eq!(error.message(), "Invalid character");
+    }
+
+    #[test]
+    fn comments() {
+        let source = "a{# note {{ name }}\n # } #}b{{ x }}{##}";
+        let tokens = Tokenizer::new(source.as_bytes()).tokenize().unwrap();
+        let tokens: Vec<(TokenType, &[u8])> = tokens.iter().map(|token| (token.token_type, token.token_value)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::TempalteLiteral, "a".as_bytes()),
+            (TokenType::TempalteLiteral, "b".as_bytes()),
+            (TokenType::DoubleLeftBrackets, "{{".as_bytes()),
+            (TokenType::Identifier, "x".as_bytes()),
+            (TokenType::DoubleRightBrackets, "}}".as_bytes())
+        ]);
+
+        let tokens = Tokenizer::new(source.as_bytes()).with_comments(true).tokenize().unwrap();
+        let comments: Vec<&[u8]> = tokens.iter()
+            .filter(|token| token.token_type == TokenType::Comment)
+            .map(|token| token.token_value)
+            .collect();
+        assert_eq!(comments, vec!["{# note {{ name }}\n # } #}".as_bytes(), "{##}".as_bytes()]);
+
+        let error = Tokenizer::new("a\n {# note".as_bytes()).tokenize().unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 3, end: 5, line: 2, column: 2 }));
+        assert_eq!(error.message(), "Unterminated comment");
+        let error = Tokenizer::new("{#".as_bytes()).tokenize().unwrap_err();
+        assert_eq!((error.message(), error.span()), ("Unterminated comment", Some(Span { start: 0, end: 2, line: 1, column: 1 })));
+        let error = Tokenizer::new("a{# b".as_bytes()).tokenize().unwrap_err();
+        assert_eq!((error.message(), error.span()), ("Unterminated comment", Some(Span { start: 1, end: 3, line: 1, column: 2 })));
+    }
+
+    #[test]
+    fn raw_blocks() {
+        let source = "<p>{{raw}}{{ name }}\n{# x #}{{ endraw }}</p> \\{{ x }} a\\{{{{ x }}";
+        let tokens = Tokenizer::new(source.as_bytes()).tokenize().unwrap();
+        let tokens: Vec<(TokenType, &[u8])> = tokens.iter().map(|token| (token.token_type, token.token_value)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::TempalteLiteral, "<p>".as_bytes()),
+            (TokenType::TempalteLiteral, "{{ name }}\n{# x #}".as_bytes()),
+            (TokenType::TempalteLiteral, "</p> ".as_bytes()),
+            (TokenType::TempalteLiteral, "{{ x }} a".as_bytes()),
+            (TokenType::TempalteLiteral, "{{".as_bytes()),
+            (TokenType::DoubleLeftBrackets, "{{".as_bytes()),
+            (TokenType::Identifier, "x".as_bytes()),
+            (TokenType::DoubleRightBrackets, "}}".as_bytes())
+        ]);
+
+        let tokens = Tokenizer::new(r"C:\{{ dir }} C:\\{{ dir }}\\{{ x }}".as_bytes()).tokenize().unwrap();
+        let tokens: Vec<(TokenType, &[u8])> = tokens.iter().map(|token| (token.token_type, token.token_value)).collect();
+        assert_eq!(tokens, vec![
+            (TokenType::TempalteLiteral, "C:".as_bytes()),
+            (TokenType::TempalteLiteral, "{{ dir }} C:\\".as_bytes()),
+            (TokenType::DoubleLeftBrackets, "{{".as_bytes()),
+            (TokenType::Identifier, "dir".as_bytes()),
+            (TokenType::DoubleRightBrackets, "}}".as_bytes()),
+            (TokenType::TempalteLiteral, "\\".as_bytes()),
+            (TokenType::DoubleLeftBrackets, "{{".as_bytes()),
+            (TokenType::Identifier, "x".as_bytes()),
+            (TokenType::DoubleRightBrackets, "}}".as_bytes())
+        ]);
+
+        let error = Tokenizer::new("a {{ raw }} {{ b }}".as_bytes()).tokenize().unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 2, end: 11, line: 1, column: 3 }));
+        assert_eq!(error.message(), "Unterminated raw block");
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = Tokenizer::new("é\n  {{ a.b }}".as_bytes()).tokenize().unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span()).collect();
+        assert_eq!(spans, vec![
+            Span { start: 0, end: 5, line: 1, column: 1 },
+            Span { start: 5, end: 7, line: 2, column: 3 },
+            Span { start: 8, end: 9, line: 2, column: 6 },
+            Span { start: 9, end: 10, line: 2, column: 7 },
+            Span { start: 10, end: 11, line: 2, column: 8 },
+            Span { start: 12, end: 14, line: 2, column: 10 }
+        ]);
+
+        let tokens = Tokenizer::new("é{{ a }}ü{{ b }}".as_bytes()).tokenize().unwrap();
+        let columns: Vec<usize> = tokens.iter().map(|token| token.span().column).collect();
+        assert_eq!(columns, vec![1, 2, 5, 7, 9, 10, 13, 15]);
     }
 }
\ No newline at end of file